@@ -0,0 +1,384 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use parking_lot::Mutex;
+
+use tchat_protocol::{is_valid_room_name, SignalData, SignalHeader, SignalType, DEFAULT_ROOM};
+
+use crate::{
+  errors::CommandError,
+  message::ACTION_PREFIX,
+  state::{LastSeen, Members}
+};
+
+/// Что клиент делает после команды
+pub enum Action {
+  Send(Vec<SignalData>),
+  /// Текст только для себя, на сервер ничего не уходит
+  Info(String),
+  Clear,
+  ToggleSidebar,
+  Quit,
+}
+
+/// Что команды видят и могут менять в клиенте
+pub struct Context<'a> {
+  /// Комната, в которую уходят набранные сообщения
+  pub room: &'a Mutex<String>,
+  /// Сколько сообщений истории просить при входе в комнату
+  pub history: usize,
+  /// Насколько давнюю историю просить, как `--history-since`
+  pub history_since: Option<Duration>,
+  pub members: &'a Mutex<Members>,
+  pub last_seen: &'a Mutex<LastSeen>,
+}
+
+/// Одна команда со слешем, `/name usage`
+pub struct Command {
+  pub name: &'static str,
+  pub usage: &'static str,
+  pub help: &'static str,
+  run: fn(&Context, Arguments) -> Result<Action, CommandError>,
+}
+
+impl Command {
+  pub fn run(&self, context: &Context, arguments: Arguments) -> Result<Action, CommandError> {
+    (self.run)(context, arguments)
+  }
+}
+
+/// Текст после имени команды, читается по словам
+pub struct Arguments<'a>(&'a str);
+
+impl<'a> Arguments<'a> {
+  pub fn next(&mut self) -> Option<&'a str> {
+    let rest = self.0.trim_start();
+    if rest.is_empty() {
+      return None;
+    }
+    let (word, rest) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+    self.0 = rest;
+    Some(word)
+  }
+
+  pub fn required(&mut self) -> Result<&'a str, CommandError> {
+    self.next().ok_or(CommandError::Usage)
+  }
+
+  /// Все, что осталось, для свободного текста вроде сообщения
+  pub fn rest(self) -> &'a str {
+    self.0.trim()
+  }
+
+  /// Ошибка, если что-то осталось
+  pub fn end(self) -> Result<(), CommandError> {
+    match self.rest().is_empty() {
+      true => Ok(()),
+      false => Err(CommandError::Usage)
+    }
+  }
+}
+
+/// Набранная строка: текст для отправки, известная команда или неизвестная
+pub enum Input<'a> {
+  Text(&'a str),
+  Command(&'static Command, Arguments<'a>),
+  Unknown(&'a str),
+}
+
+/// Строки с `/` - команды, `//` отправляет текст с одним слешем в начале
+pub fn parse(input: &str) -> Input<'_> {
+  let Some(line) = input.strip_prefix('/') else {
+    return Input::Text(input)
+  };
+  if line.starts_with('/') {
+    return Input::Text(line);
+  }
+
+  let (name, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+  match COMMANDS.iter().find(|v| v.name == name) {
+    Some(command) => Input::Command(command, Arguments(rest)),
+    None => Input::Unknown(name)
+  }
+}
+
+pub static COMMANDS: &[Command] = &[
+  Command { name: "help", usage: "", help: "Show this list", run: help },
+  Command { name: "join", usage: "<#room>", help: "Join a room or switch to a joined one", run: join },
+  Command { name: "part", usage: "[#room]", help: "Leave a room, the current one by default", run: part },
+  Command { name: "list", usage: "", help: "Show rooms on the server", run: list },
+  Command { name: "who", usage: "", help: "Show who is in the current room", run: who },
+  Command { name: "sidebar", usage: "", help: "Show or hide the member list, also F2", run: sidebar },
+  Command { name: "nick", usage: "<name>", help: "Change your name", run: nick },
+  Command { name: "msg", usage: "<user> <text>", help: "Send a direct message", run: msg },
+  Command { name: "me", usage: "<text>", help: "Describe what you are doing", run: me },
+  Command { name: "clear", usage: "", help: "Clear the chat on this screen", run: clear },
+  Command { name: "quit", usage: "", help: "Close the chat", run: quit },
+];
+
+fn help(_: &Context, arguments: Arguments) -> Result<Action, CommandError> {
+  arguments.end()?;
+  let lines = COMMANDS.iter()
+    .map(|v| match v.usage.is_empty() {
+      true => format!("/{} - {}", v.name, v.help),
+      false => format!("/{} {} - {}", v.name, v.usage, v.help)
+    })
+    .collect::<Vec<String>>()
+    .join("\n");
+  Ok(Action::Info(format!("Commands, start a message with // to send a leading /:\n{lines}")))
+}
+
+fn join(context: &Context, mut arguments: Arguments) -> Result<Action, CommandError> {
+  let room = arguments.required()?;
+  arguments.end()?;
+  // С неверным названием сервер откажет, а сообщения уходили бы в никуда
+  if !is_valid_room_name(room) {
+    return Err(CommandError::Refused(format!("Invalid room name {room}, rooms look like #rust")));
+  }
+  *context.room.lock() = room.to_owned();
+  // В комнате уже есть, просто переключаемся на нее
+  if context.members.lock().has(room) {
+    return Ok(Action::Info(format!("Now talking in {room}")));
+  }
+  let mut headers = vec![
+    SignalHeader::SignalType(SignalType::Join),
+    SignalHeader::Room(room.to_owned())
+  ];
+  headers.extend(history_headers(context.history, context.history_since));
+  // В комнате уже были: сервер пришлет только пропущенное или скажет, сколько
+  // пропущено
+  if let Some(seq) = context.last_seen.lock().get(room) {
+    headers.push(SignalHeader::After(seq));
+  }
+  Ok(Action::Send(vec![SignalData::new(headers, None), user_list(room)]))
+}
+
+fn part(context: &Context, mut arguments: Arguments) -> Result<Action, CommandError> {
+  let mut room = context.room.lock();
+  let target = match arguments.next() {
+    Some(v) => v.to_owned(),
+    None => room.clone()
+  };
+  arguments.end()?;
+  // Сервер все равно откажет, а список участников лобби уже был бы стерт
+  if target == DEFAULT_ROOM {
+    return Err(CommandError::Refused(format!("You can't leave {DEFAULT_ROOM}")));
+  }
+  if target == *room {
+    *room = DEFAULT_ROOM.to_owned();
+  }
+  context.members.lock().forget(&target);
+  Ok(Action::Send(vec![SignalData::new(
+    vec![
+      SignalHeader::SignalType(SignalType::Part),
+      SignalHeader::Room(target)
+    ],
+    None
+  )]))
+}
+
+fn list(_: &Context, arguments: Arguments) -> Result<Action, CommandError> {
+  arguments.end()?;
+  Ok(Action::Send(vec![SignalData::new(
+    vec![SignalHeader::SignalType(SignalType::List)],
+    None
+  )]))
+}
+
+fn who(context: &Context, arguments: Arguments) -> Result<Action, CommandError> {
+  arguments.end()?;
+  let room = context.room.lock().clone();
+  let members = context.members.lock();
+  let users = members.of(&room);
+  Ok(Action::Info(format!("{room} ({}): {}", users.len(), users.join(", "))))
+}
+
+fn sidebar(_: &Context, arguments: Arguments) -> Result<Action, CommandError> {
+  arguments.end()?;
+  Ok(Action::ToggleSidebar)
+}
+
+fn nick(_: &Context, mut arguments: Arguments) -> Result<Action, CommandError> {
+  let username = arguments.required()?;
+  arguments.end()?;
+  // Имя меняется, когда сервер разошлет переименование, до этого он
+  // может отказать, если имя занято
+  Ok(Action::Send(vec![SignalData::new(
+    vec![
+      SignalHeader::SignalType(SignalType::Rename),
+      SignalHeader::NewUsername(username.to_owned())
+    ],
+    None
+  )]))
+}
+
+fn msg(_: &Context, mut arguments: Arguments) -> Result<Action, CommandError> {
+  let target = arguments.required()?;
+  let text = arguments.rest();
+  if text.is_empty() {
+    return Err(CommandError::Usage);
+  }
+  Ok(Action::Send(vec![SignalData::new(
+    vec![
+      SignalHeader::SignalType(SignalType::DirectMessage),
+      SignalHeader::Target(target.to_owned()),
+      SignalHeader::WithMessage
+    ],
+    Some(text)
+  )]))
+}
+
+fn me(context: &Context, arguments: Arguments) -> Result<Action, CommandError> {
+  let text = arguments.rest();
+  if text.is_empty() {
+    return Err(CommandError::Usage);
+  }
+  Ok(Action::Send(vec![chat_message(context, &format!("{ACTION_PREFIX}{text}"))]))
+}
+
+fn clear(_: &Context, arguments: Arguments) -> Result<Action, CommandError> {
+  arguments.end()?;
+  Ok(Action::Clear)
+}
+
+fn quit(_: &Context, arguments: Arguments) -> Result<Action, CommandError> {
+  arguments.end()?;
+  Ok(Action::Quit)
+}
+
+/// Обычное сообщение в текущую комнату. Отправителя сервер берет из
+/// соединения, поэтому USERNAME не нужен и не ломается после /nick
+pub fn chat_message(context: &Context, text: &str) -> SignalData {
+  SignalData::new(
+    vec![
+      SignalHeader::SignalType(SignalType::NewMessage),
+      SignalHeader::Room(context.room.lock().clone()),
+      SignalHeader::WithMessage
+    ],
+    Some(text)
+  )
+}
+
+/// LIMIT и, если задан возраст, SINCE для запроса истории
+pub fn history_headers(limit: usize, since: Option<Duration>) -> Vec<SignalHeader> {
+  let mut headers = vec![SignalHeader::Limit(limit)];
+  if let Some(age) = since {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    headers.push(SignalHeader::Since(now.saturating_sub(age).as_secs()));
+  }
+  headers
+}
+
+/// Запрос списка пользователей комнаты для боковой панели
+pub fn user_list(room: &str) -> SignalData {
+  SignalData::new(
+    vec![
+      SignalHeader::SignalType(SignalType::UserList),
+      SignalHeader::Room(room.to_owned())
+    ],
+    None
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::Duration;
+  use parking_lot::Mutex;
+
+  use tchat_protocol::{SignalData, SignalHeader, SignalType, DEFAULT_ROOM};
+
+  use super::{history_headers, parse, Action, Arguments, Context, Input};
+  use crate::{errors::CommandError, state::{LastSeen, Members}};
+
+  fn run(input: &str, room: &Mutex<String>, members: &Mutex<Members>) -> Result<Action, CommandError> {
+    let last_seen = Mutex::new(LastSeen::default());
+    let context = Context { room, history: 20, history_since: None, members, last_seen: &last_seen };
+    match parse(input) {
+      Input::Command(command, arguments) => command.run(&context, arguments),
+      _ => panic!("{input} is not a command")
+    }
+  }
+
+  #[test]
+  fn text_and_escaped_slash_are_sent_as_is() {
+    assert!(matches!(parse("привет"), Input::Text("привет")));
+    assert!(matches!(parse("//etc/hosts"), Input::Text("/etc/hosts")));
+    assert!(matches!(parse("/nope x"), Input::Unknown("nope")));
+  }
+
+  #[test]
+  fn arguments_are_read_word_by_word() {
+    let Input::Command(command, mut arguments) = parse("/msg anna  hi  there ") else {
+      panic!("/msg is not parsed")
+    };
+    assert_eq!(command.name, "msg");
+    assert_eq!(arguments.next(), Some("anna"));
+    assert_eq!(arguments.rest(), "hi  there");
+
+    assert!(Arguments("  ").end().is_ok());
+    assert!(Arguments(" extra").end().is_err());
+    assert!(Arguments("").required().is_err());
+  }
+
+  #[test]
+  fn wrong_arguments_show_usage() {
+    let room = Mutex::new(DEFAULT_ROOM.to_owned());
+    let members = Mutex::new(Members::default());
+    assert!(matches!(run("/help me", &room, &members), Err(CommandError::Usage)));
+    assert!(matches!(run("/msg anna", &room, &members), Err(CommandError::Usage)));
+    assert!(matches!(run("/me", &room, &members), Err(CommandError::Usage)));
+    assert!(matches!(run("/quit now", &room, &members), Err(CommandError::Usage)));
+    assert!(matches!(run("/quit", &room, &members), Ok(Action::Quit)));
+  }
+
+  #[test]
+  fn bad_rooms_are_refused_locally() {
+    let room = Mutex::new(DEFAULT_ROOM.to_owned());
+    let members = Mutex::new(Members::default());
+    assert!(matches!(run("/join rust", &room, &members), Err(CommandError::Refused(_))));
+    assert!(matches!(run("/part", &room, &members), Err(CommandError::Refused(_))));
+    assert_eq!(*room.lock(), DEFAULT_ROOM);
+
+    assert!(matches!(run("/join #rust", &room, &members), Ok(Action::Send(_))));
+    assert_eq!(*room.lock(), "#rust");
+  }
+
+  #[test]
+  fn history_since_adds_a_timestamp() {
+    assert_eq!(history_headers(50, None), [SignalHeader::Limit(50)]);
+    let headers = history_headers(50, Some(Duration::from_secs(60 * 60)));
+    let [SignalHeader::Limit(50), SignalHeader::Since(since)] = headers[..] else {
+      panic!("unexpected headers {headers:?}")
+    };
+    let now = chrono::Utc::now().timestamp() as u64;
+    assert!((now - 60 * 60 - 1..=now - 60 * 60 + 1).contains(&since));
+  }
+
+  #[test]
+  fn rejoin_asks_only_for_missed_messages() {
+    let room = Mutex::new(DEFAULT_ROOM.to_owned());
+    let members = Mutex::new(Members::default());
+    let last_seen = Mutex::new(LastSeen::default());
+    last_seen.lock().apply(&SignalData::new(
+      vec![
+        SignalHeader::SignalType(SignalType::History),
+        SignalHeader::Room("#rust".to_owned()),
+        SignalHeader::Seq(7),
+        SignalHeader::WithMessage
+      ],
+      Some("hi")
+    ));
+    let context = Context { room: &room, history: 20, history_since: None, members: &members, last_seen: &last_seen };
+
+    let join = |room: &str| {
+      let input = format!("/join {room}");
+      let Input::Command(command, arguments) = parse(&input) else {
+        panic!("/join is not parsed")
+      };
+      match command.run(&context, arguments) {
+        Ok(Action::Send(signals)) => signals[0].after,
+        _ => panic!("/join {room} is not sent")
+      }
+    };
+    assert_eq!(join("#rust"), Some(7));
+    assert_eq!(join("#go"), None);
+  }
+}
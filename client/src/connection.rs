@@ -1,37 +1,53 @@
 use std::{
+  str::FromStr,
+  sync::Arc,
   net::TcpStream, 
   io::{
     self, 
     Write,
-    Error, 
-    ErrorKind, BufRead, BufReader
+    BufReader
   },
 };
 
-use crate::types::{
+use tchat_protocol::{
+  PROTOCOL_VERSION,
   SignalType, 
   SignalHeader, 
   SignalData,
-  AuthStatus
+  SignalReader,
+  AuthStatus,
+  AuthConnectionError,
+  DenialReason
 };
 
+use parking_lot::Mutex;
+
+use crate::errors::ConnectionError;
+
 pub struct Connection {
   pub stream: TcpStream,
-  reader: io::BufReader<TcpStream>
+  /// Общий для всех копий соединения: в буфере могут лежать уже
+  /// прочитанные из сокета сигналы
+  reader: Arc<Mutex<BufReader<TcpStream>>>
 }
 
 impl Connection {
-  pub fn new(address: &str, username: &str) -> io::Result<Connection> {
-    let signal = SignalData::new(
-      vec![
-        SignalHeader::SignalType(SignalType::Connection),
-        SignalHeader::Username(username.to_owned())
-      ],
-      None
-    );
+  /// `history` - хедеры запроса истории лобби, которую сервер пришлет сразу
+  /// после подключения
+  pub fn new(address: &str, username: &str, key: Option<&str>, history: Vec<SignalHeader>) -> Result<Connection, ConnectionError> {
+    let mut headers = vec![
+      SignalHeader::SignalType(SignalType::Connection),
+      SignalHeader::ProtocolVersion(PROTOCOL_VERSION),
+      SignalHeader::Username(username.to_owned())
+    ];
+    headers.extend(history);
+    if let Some(v) = key {
+      headers.push(SignalHeader::Key(v.to_owned()));
+    }
+    let signal = SignalData::new(headers, None);
     let mut connection = TcpStream::connect(address)?;
     connection.write_all(signal.to_string().as_bytes())?;
-    let reader = BufReader::new(connection.try_clone()?);
+    let reader = Arc::new(Mutex::new(BufReader::new(connection.try_clone()?)));
 
     let mut instance = Connection {
       stream: connection,
@@ -39,34 +55,29 @@ impl Connection {
     };
 
     let data_from_socket = instance.read_signal()?;
-    if data_from_socket.contains(&AuthStatus::DENIED.to_string()) {
-      return Err(Error::new(ErrorKind::ConnectionAborted, "Access denied"));
+    let response = SignalData::from_str(&data_from_socket)
+      .map_err(|_| ConnectionError::InvalidResponse)?;
+    match response.auth_status {
+      Some(AuthStatus::ACCEPTED) => {},
+      Some(AuthStatus::DENIED) => {
+        // Причина может не прийти, тогда так и пишем
+        return Err(ConnectionError::Denied(AuthConnectionError {
+          reason: response.denial_reason.unwrap_or(DenialReason::InvalidSignal),
+          text: response.message.unwrap_or("the server gave no reason".to_owned())
+        }));
+      },
+      None => return Err(ConnectionError::InvalidResponse)
     }
-  
-    return Ok(instance)
+
+    Ok(instance)
   }
 
   pub fn read_signal(&mut self) -> io::Result<String> {
-    let mut res_line = String::new();
-    let mut headers_read = false;
-    loop {
-      let mut buf_line = String::new();
-      match self.reader.read_line(&mut buf_line) {
-        Err(e) => panic!("Got an error: {}", e),
-        Ok(0) => return Err(Error::new(ErrorKind::BrokenPipe, "Connection closed")),
-        Ok(_) => (),
-      };
-      res_line.push_str(&buf_line);
-  
-      if res_line.ends_with("\r\n\r\n"){
-        if !res_line.contains(&SignalHeader::WithMessage.to_string()) || headers_read {
-          break;
-        }
-        headers_read = true;
-      }
-    }
-  
-    Ok(res_line)
+    self.reader.lock().read_signal()
+  }
+
+  pub fn send_signal(&mut self, signal: &SignalData) -> io::Result<()> {
+    self.stream.write_all(signal.to_string().as_bytes())
   }
 }
 
@@ -74,7 +85,7 @@ impl Clone for Connection {
   fn clone(&self) -> Self {
     Connection { 
       stream: self.stream.try_clone().unwrap(), 
-      reader: BufReader::new(self.stream.try_clone().unwrap())
+      reader: self.reader.clone()
     }
   }
 }
\ No newline at end of file
@@ -0,0 +1,395 @@
+use std::io;
+
+use termion::event::{Event, Key};
+
+use crate::{
+  history::{InputHistory, Search},
+  wrap::{display_width, head}
+};
+
+/// Набранный в строке ввода текст и курсор в нем, с обычным редактированием как
+/// в readline
+#[derive(Debug, Default)]
+pub struct LineEditor {
+  text: String,
+  /// Байт в `text`, всегда на границе символа
+  cursor: usize,
+  /// Последний вырезанный текст для Ctrl-Y
+  kill_buffer: String,
+  history: InputHistory,
+}
+
+impl LineEditor {
+  pub fn new(history: InputHistory) -> LineEditor {
+    LineEditor { history, ..Default::default() }
+  }
+
+  pub fn text(&self) -> &str {
+    &self.text
+  }
+
+  /// Позиция курсора в байтах от начала `text()`
+  pub fn cursor(&self) -> usize {
+    self.cursor
+  }
+
+  /// Поиск по Ctrl-R, если он идет
+  pub fn search(&self) -> Option<&Search> {
+    self.history.search()
+  }
+
+  /// Запоминает набранный текст в истории и очищает ввод
+  pub fn submit(&mut self) -> io::Result<()> {
+    self.history.accept_search();
+    let text = std::mem::take(&mut self.text);
+    self.cursor = 0;
+    self.history.push(text.trim())
+  }
+
+  /// Применяет клавишу редактирования. Возвращает false для событий не для
+  /// редактора, например Enter или прокрутки
+  pub fn handle(&mut self, event: &Event) -> bool {
+    if self.history.search().is_some() && self.handle_search(event) {
+      return true;
+    }
+
+    match event {
+      Event::Key(Key::Char('\n')) => return false,
+      // Alt-Enter, Enter приходит как '\r'
+      Event::Key(Key::Alt('\r')) | Event::Key(Key::Alt('\n')) => self.insert('\n'),
+      Event::Key(Key::Char(c)) => self.insert(*c),
+      Event::Key(Key::Backspace) | Event::Key(Key::Ctrl('h')) => self.backspace(),
+      Event::Key(Key::Delete) | Event::Key(Key::Ctrl('d')) => self.delete(),
+      Event::Key(Key::Left) | Event::Key(Key::Ctrl('b')) => self.left(),
+      Event::Key(Key::Right) | Event::Key(Key::Ctrl('f')) => self.right(),
+      Event::Key(Key::Home) | Event::Key(Key::Ctrl('a')) => self.home(),
+      Event::Key(Key::End) | Event::Key(Key::Ctrl('e')) => self.end(),
+      Event::Key(Key::Alt('b')) => self.word_left(),
+      Event::Key(Key::Alt('f')) => self.word_right(),
+      Event::Key(Key::Alt('d')) => self.kill_word_right(),
+      Event::Key(Key::Ctrl('w')) | Event::Key(Key::Alt('\x7f')) => self.kill_word_left(),
+      Event::Key(Key::Ctrl('u')) => self.kill_to_start(),
+      Event::Key(Key::Ctrl('k')) => self.kill_to_end(),
+      Event::Key(Key::Ctrl('y')) => self.yank(),
+      // В многострочном вводе сначала ходим по строкам, потом по истории
+      Event::Key(Key::Up) | Event::Key(Key::Ctrl('p')) => {
+        if self.line_up() {
+          return true;
+        }
+        if let Some(v) = self.history.older(&self.text) {
+          let v = v.to_owned();
+          self.set_text(v);
+        }
+      },
+      Event::Key(Key::Down) | Event::Key(Key::Ctrl('n')) => {
+        if self.line_down() {
+          return true;
+        }
+        if let Some(v) = self.history.newer() {
+          let v = v.to_owned();
+          self.set_text(v);
+        }
+      },
+      Event::Key(Key::Ctrl('r')) => self.history.start_search(&self.text),
+      // Ctrl и Alt со стрелками termion не разбирает
+      Event::Unsupported(v) => match v.as_slice() {
+        b"\x1b[1;5D" | b"\x1b[1;3D" => self.word_left(),
+        b"\x1b[1;5C" | b"\x1b[1;3C" => self.word_right(),
+        // Shift-Enter в терминалах с CSI u или modifyOtherKeys
+        b"\x1b[13;2u" | b"\x1b[27;2;13~" => self.insert('\n'),
+        _ => return false
+      },
+      _ => return false
+    }
+    true
+  }
+
+  /// Клавиши во время Ctrl-R. Остальные завершают поиск, оставляя
+  /// найденное, и дальше обрабатываются как обычно
+  fn handle_search(&mut self, event: &Event) -> bool {
+    let Some(search) = self.history.search() else {
+      return false
+    };
+    let mut query = search.query.clone();
+
+    let found = match event {
+      Event::Key(Key::Char('\n')) => {
+        self.history.accept_search();
+        return false
+      },
+      Event::Key(Key::Char(c)) => {
+        query.push(*c);
+        self.history.update_search(query.clone())
+      },
+      Event::Key(Key::Backspace) => {
+        query.pop();
+        self.history.update_search(query.clone())
+      },
+      Event::Key(Key::Ctrl('r')) => self.history.search_older(),
+      Event::Key(Key::Ctrl('g')) | Event::Key(Key::Esc) => {
+        if let Some(v) = self.history.cancel_search() {
+          self.set_text(v);
+        }
+        return true
+      },
+      _ => {
+        self.history.accept_search();
+        return false
+      }
+    };
+
+    if let Some(v) = found {
+      let v = v.to_owned();
+      // Курсор на найденном месте, как в readline
+      let cursor = v.find(&query).unwrap_or(v.len());
+      self.set_text(v);
+      self.cursor = cursor;
+    }
+    true
+  }
+
+  fn set_text(&mut self, text: String) {
+    self.cursor = text.len();
+    self.text = text;
+  }
+
+  pub fn insert(&mut self, c: char) {
+    self.text.insert(self.cursor, c);
+    self.cursor += c.len_utf8();
+  }
+
+  pub fn insert_str(&mut self, text: &str) {
+    self.text.insert_str(self.cursor, text);
+    self.cursor += text.len();
+  }
+
+  /// Удаляет символ перед курсором
+  pub fn backspace(&mut self) {
+    let start = self.prev_char();
+    self.text.replace_range(start..self.cursor, "");
+    self.cursor = start;
+  }
+
+  /// Удаляет символ под курсором
+  pub fn delete(&mut self) {
+    let end = self.next_char();
+    self.text.replace_range(self.cursor..end, "");
+  }
+
+  pub fn left(&mut self) {
+    self.cursor = self.prev_char();
+  }
+
+  pub fn right(&mut self) {
+    self.cursor = self.next_char();
+  }
+
+  /// Начало текущей строки
+  pub fn home(&mut self) {
+    self.cursor = self.line_start();
+  }
+
+  /// Конец текущей строки
+  pub fn end(&mut self) {
+    self.cursor = self.line_end();
+  }
+
+  /// Переходит на строку выше в той же колонке. Возвращает false на первой
+  /// строке
+  fn line_up(&mut self) -> bool {
+    let start = self.line_start();
+    if start == 0 {
+      return false;
+    }
+    let column = display_width(&self.text[start..self.cursor]);
+    let above = self.text[..start - 1].rfind('\n').map_or(0, |v| v + 1);
+    self.cursor = above + head(&self.text[above..start - 1], column).len();
+    true
+  }
+
+  /// Переходит на строку ниже в той же колонке. Возвращает false на последней
+  /// строке
+  fn line_down(&mut self) -> bool {
+    let end = self.line_end();
+    if end == self.text.len() {
+      return false;
+    }
+    let column = display_width(&self.text[self.line_start()..self.cursor]);
+    let below = &self.text[end + 1..];
+    let below = &below[..below.find('\n').unwrap_or(below.len())];
+    self.cursor = end + 1 + head(below, column).len();
+    true
+  }
+
+  pub fn word_left(&mut self) {
+    self.cursor = self.prev_word();
+  }
+
+  pub fn word_right(&mut self) {
+    self.cursor = self.next_word();
+  }
+
+  /// Ctrl-W, вырезает слово перед курсором
+  pub fn kill_word_left(&mut self) {
+    self.kill(self.prev_word()..self.cursor);
+  }
+
+  /// Alt-D, вырезает слово после курсора
+  pub fn kill_word_right(&mut self) {
+    self.kill(self.cursor..self.next_word());
+  }
+
+  /// Ctrl-U, вырезает до начала строки
+  pub fn kill_to_start(&mut self) {
+    self.kill(self.line_start()..self.cursor);
+  }
+
+  /// Ctrl-K, вырезает до конца строки
+  pub fn kill_to_end(&mut self) {
+    self.kill(self.cursor..self.line_end());
+  }
+
+  /// Вставляет последний вырезанный текст, Ctrl-Y
+  pub fn yank(&mut self) {
+    let text = self.kill_buffer.clone();
+    self.insert_str(&text);
+  }
+
+  fn kill(&mut self, range: std::ops::Range<usize>) {
+    if range.is_empty() {
+      return;
+    }
+    self.cursor = range.start;
+    self.kill_buffer = self.text.drain(range).collect();
+  }
+
+  fn line_start(&self) -> usize {
+    self.text[..self.cursor].rfind('\n').map_or(0, |v| v + 1)
+  }
+
+  fn line_end(&self) -> usize {
+    self.text[self.cursor..].find('\n').map_or(self.text.len(), |v| self.cursor + v)
+  }
+
+  fn prev_char(&self) -> usize {
+    match self.text[..self.cursor].chars().next_back() {
+      Some(c) => self.cursor - c.len_utf8(),
+      None => self.cursor
+    }
+  }
+
+  fn next_char(&self) -> usize {
+    match self.text[self.cursor..].chars().next() {
+      Some(c) => self.cursor + c.len_utf8(),
+      None => self.cursor
+    }
+  }
+
+  /// Начало слова перед курсором: пропускаем пробелы, потом само слово
+  fn prev_word(&self) -> usize {
+    let before = self.text[..self.cursor].trim_end();
+    match before.rfind(char::is_whitespace) {
+      Some(index) => index + before[index..].chars().next().map_or(0, char::len_utf8),
+      None => 0
+    }
+  }
+
+  /// Конец слова после курсора
+  fn next_word(&self) -> usize {
+    let after = &self.text[self.cursor..];
+    let skipped = after.len() - after.trim_start().len();
+    match after[skipped..].find(char::is_whitespace) {
+      Some(index) => self.cursor + skipped + index,
+      None => self.text.len()
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use termion::event::{Event, Key};
+
+  use super::LineEditor;
+
+  fn editor(text: &str) -> LineEditor {
+    let mut editor = LineEditor::default();
+    editor.insert_str(text);
+    editor
+  }
+
+  fn press(editor: &mut LineEditor, keys: &[Key]) {
+    for key in keys {
+      editor.handle(&Event::Key(*key));
+    }
+  }
+
+  #[test]
+  fn backspace_and_delete_remove_whole_characters() {
+    let mut e = editor("привет😀");
+    press(&mut e, &[Key::Backspace]);
+    assert_eq!(e.text(), "привет");
+    assert_eq!(e.cursor(), "привет".len());
+
+    press(&mut e, &[Key::Left, Key::Left, Key::Delete]);
+    assert_eq!(e.text(), "привт");
+    assert_eq!(e.cursor(), "прив".len());
+  }
+
+  #[test]
+  fn cursor_steps_over_emoji() {
+    let mut e = editor("a👍b");
+    press(&mut e, &[Key::Left, Key::Left]);
+    assert_eq!(e.cursor(), 1);
+    press(&mut e, &[Key::Backspace]);
+    assert_eq!(e.text(), "👍b");
+    assert_eq!(e.cursor(), 0);
+    press(&mut e, &[Key::Right]);
+    assert_eq!(e.cursor(), "👍".len());
+  }
+
+  #[test]
+  fn word_motions_on_cyrillic() {
+    let mut e = editor("мама мыла раму");
+    press(&mut e, &[Key::Alt('b')]);
+    assert_eq!(e.cursor(), "мама мыла ".len());
+    press(&mut e, &[Key::Alt('b')]);
+    assert_eq!(e.cursor(), "мама ".len());
+    press(&mut e, &[Key::Alt('f')]);
+    assert_eq!(e.cursor(), "мама мыла".len());
+    press(&mut e, &[Key::Alt('d')]);
+    assert_eq!(e.text(), "мама мыла");
+  }
+
+  #[test]
+  fn killed_text_comes_back_with_yank() {
+    let mut e = editor("你好 世界");
+    press(&mut e, &[Key::Ctrl('w')]);
+    assert_eq!(e.text(), "你好 ");
+    press(&mut e, &[Key::Ctrl('a'), Key::Ctrl('k')]);
+    assert_eq!(e.text(), "");
+    press(&mut e, &[Key::Ctrl('y')]);
+    assert_eq!(e.text(), "你好 ");
+    assert_eq!(e.cursor(), "你好 ".len());
+  }
+
+  #[test]
+  fn lines_keep_the_screen_column() {
+    // 你 занимает две колонки, поэтому колонка 3 попадает на его правый край
+    let mut e = editor("你好世界\nabc");
+    press(&mut e, &[Key::Up]);
+    assert_eq!(e.cursor(), "你".len());
+    press(&mut e, &[Key::Down]);
+    assert_eq!(e.cursor(), "你好世界\nab".len());
+    press(&mut e, &[Key::Ctrl('u')]);
+    assert_eq!(e.text(), "你好世界\nc");
+  }
+
+  #[test]
+  fn alt_enter_adds_a_line_and_enter_is_left_to_the_caller() {
+    let mut e = editor("один");
+    assert!(e.handle(&Event::Key(Key::Alt('\r'))));
+    press(&mut e, &[Key::Char('д')]);
+    assert!(!e.handle(&Event::Key(Key::Char('\n'))));
+    assert_eq!(e.text(), "один\nд");
+  }
+}
@@ -0,0 +1,50 @@
+use std::{
+  fmt,
+  io,
+  error::Error
+};
+
+use tchat_protocol::AuthConnectionError;
+
+#[derive(Debug)]
+pub enum ConnectionError {
+  Io(io::Error),
+  Denied(AuthConnectionError),
+  InvalidResponse
+}
+
+impl Error for ConnectionError {}
+impl fmt::Display for ConnectionError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      ConnectionError::Io(e) => write!(f, "Connection error: {e}"),
+      ConnectionError::Denied(e) => write!(f, "Access denied: {e}"),
+      ConnectionError::InvalidResponse => write!(f, "Invalid server response")
+    }
+  }
+}
+
+impl From<io::Error> for ConnectionError {
+  fn from(e: io::Error) -> Self {
+    ConnectionError::Io(e)
+  }
+}
+
+/// Ошибка в аргументах команды, показывается только у себя
+#[derive(Debug)]
+pub enum CommandError {
+  /// Не те аргументы, в ответ показывается usage команды
+  Usage,
+  /// Команда понятна, но выполнить ее нельзя, показывается текст
+  Refused(String),
+}
+
+impl Error for CommandError {}
+impl fmt::Display for CommandError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      CommandError::Usage => write!(f, "Wrong arguments"),
+      CommandError::Refused(text) => write!(f, "{text}")
+    }
+  }
+}
@@ -0,0 +1,241 @@
+use std::{
+  env,
+  fs::{self, File, OpenOptions},
+  io::{self, BufRead, BufReader, Write},
+  os::unix::fs::OpenOptionsExt,
+  path::PathBuf
+};
+
+/// Больше не храним ни в памяти, ни в файле
+const MAX_ENTRIES: usize = 1000;
+const HISTORY_FILE: &str = "input_history";
+/// В истории все, что набирал пользователь, в том числе личные сообщения,
+/// поэтому читать ее может только он
+const HISTORY_FILE_MODE: u32 = 0o600;
+
+/// Где история лежит по умолчанию, `$XDG_CONFIG_HOME/tchat` или
+/// `~/.config/tchat`
+pub fn default_file() -> Option<PathBuf> {
+  let config_dir = match env::var_os("XDG_CONFIG_HOME").filter(|v| !v.is_empty()) {
+    Some(v) => PathBuf::from(v),
+    None => PathBuf::from(env::var_os("HOME")?).join(".config")
+  };
+  Some(config_dir.join("tchat").join(HISTORY_FILE))
+}
+
+/// Идущий поиск по Ctrl-R
+#[derive(Debug, Clone)]
+pub struct Search {
+  pub query: String,
+  /// Найденная запись, None если ничего не нашлось
+  pub found: Option<usize>,
+  /// Строка ввода до начала поиска, возвращается при отмене
+  original: String,
+}
+
+/// Что пользователь отправлял, от старых к новым, и где в этом сейчас Up/Down и
+/// Ctrl-R
+#[derive(Debug, Default)]
+pub struct InputHistory {
+  entries: Vec<String>,
+  /// Запись, показанная по Up/Down. None - пользователь набирает новую
+  position: Option<usize>,
+  /// Набранное до первого Up, возвращается по Down
+  draft: String,
+  search: Option<Search>,
+  file: Option<PathBuf>,
+}
+
+impl InputHistory {
+  /// Загружает историю из `file` и дальше дописывает в него. Если файла нет, он
+  /// создается при первом отправленном сообщении
+  pub fn open(file: PathBuf) -> io::Result<InputHistory> {
+    let mut entries = Vec::new();
+    match File::open(&file) {
+      Ok(v) => {
+        for line in BufReader::new(v).lines() {
+          entries.push(unescape(&line?));
+        }
+      },
+      Err(e) if e.kind() == io::ErrorKind::NotFound => {},
+      Err(e) => return Err(e)
+    }
+
+    if entries.len() > MAX_ENTRIES {
+      entries.drain(..entries.len() - MAX_ENTRIES);
+      // Файл растет только дописыванием, поэтому обрезаем его при загрузке
+      let mut contents = String::new();
+      for entry in &entries {
+        contents.push_str(&escape(entry));
+        contents.push('\n');
+      }
+      fs::write(&file, contents)?;
+    }
+
+    Ok(InputHistory { entries, file: Some(file), ..Default::default() })
+  }
+
+  /// Запоминает отправленное сообщение, повтор последнего пропускается
+  pub fn push(&mut self, entry: &str) -> io::Result<()> {
+    self.position = None;
+    self.draft.clear();
+    if self.entries.last().is_some_and(|v| v == entry) {
+      return Ok(());
+    }
+
+    self.entries.push(entry.to_owned());
+    if self.entries.len() > MAX_ENTRIES {
+      self.entries.remove(0);
+    }
+
+    let Some(path) = &self.file else {
+      return Ok(())
+    };
+    if let Some(dir) = path.parent() {
+      fs::create_dir_all(dir)?;
+    }
+    let mut file = OpenOptions::new()
+      .create(true)
+      .append(true)
+      .mode(HISTORY_FILE_MODE)
+      .open(path)?;
+    writeln!(file, "{}", escape(entry))
+  }
+
+  /// Up: запись перед показанной. `current` - то, что набрано сейчас, оно
+  /// вернется, когда Down пройдет самую новую запись
+  pub fn older(&mut self, current: &str) -> Option<&str> {
+    let position = match self.position {
+      None if self.entries.is_empty() => return None,
+      None => {
+        self.draft = current.to_owned();
+        self.entries.len() - 1
+      },
+      Some(0) => return None,
+      Some(v) => v - 1
+    };
+    self.position = Some(position);
+    Some(&self.entries[position])
+  }
+
+  /// Down: запись после показанной или набранное до Up после самой новой
+  pub fn newer(&mut self) -> Option<&str> {
+    let position = self.position?;
+    match position + 1 < self.entries.len() {
+      true => {
+        self.position = Some(position + 1);
+        Some(&self.entries[position + 1])
+      },
+      false => {
+        self.position = None;
+        Some(&self.draft)
+      }
+    }
+  }
+
+  pub fn search(&self) -> Option<&Search> {
+    self.search.as_ref()
+  }
+
+  pub fn start_search(&mut self, current: &str) {
+    self.search = Some(Search {
+      query: String::new(),
+      found: None,
+      original: current.to_owned()
+    });
+  }
+
+  /// Меняет запрос и ищет самую новую запись с ним. Возвращает запись или текст
+  /// до поиска, если запрос пустой
+  pub fn update_search(&mut self, query: String) -> Option<&str> {
+    let search = self.search.as_mut()?;
+    let from = search.found.map_or(self.entries.len(), |v| v + 1);
+    search.query = query;
+    search.found = find(&self.entries, &search.query, from);
+    self.found_text()
+  }
+
+  /// Повторный Ctrl-R: следующая более старая запись с тем же запросом
+  pub fn search_older(&mut self) -> Option<&str> {
+    let search = self.search.as_mut()?;
+    let from = search.found.unwrap_or(self.entries.len());
+    if let Some(v) = find(&self.entries, &search.query, from) {
+      search.found = Some(v);
+    }
+    self.found_text()
+  }
+
+  /// Завершает поиск и возвращает текст, который был до него
+  pub fn cancel_search(&mut self) -> Option<String> {
+    self.search.take().map(|v| v.original)
+  }
+
+  /// Завершает поиск, оставляя найденную запись во вводе
+  pub fn accept_search(&mut self) {
+    if let Some(Search { found: Some(v), .. }) = self.search.take() {
+      self.position = Some(v);
+    }
+  }
+
+  fn found_text(&self) -> Option<&str> {
+    let search = self.search.as_ref()?;
+    match (search.query.is_empty(), search.found) {
+      (true, _) => Some(&search.original),
+      (false, Some(v)) => Some(&self.entries[v]),
+      // Ничего не нашлось, строка ввода остается прежней
+      (false, None) => None
+    }
+  }
+}
+
+/// Самая новая запись раньше `before`, в которой есть `query`
+fn find(entries: &[String], query: &str, before: usize) -> Option<usize> {
+  if query.is_empty() {
+    return None;
+  }
+  entries[..before.min(entries.len())].iter().rposition(|v| v.contains(query))
+}
+
+/// В файле одна запись на строку, поэтому переводы строк экранируются
+fn escape(entry: &str) -> String {
+  entry.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+fn unescape(line: &str) -> String {
+  let mut entry = String::with_capacity(line.len());
+  let mut chars = line.chars();
+  while let Some(c) = chars.next() {
+    match c {
+      '\\' => match chars.next() {
+        Some('n') => entry.push('\n'),
+        Some(v) => entry.push(v),
+        None => entry.push('\\')
+      },
+      _ => entry.push(c)
+    }
+  }
+  entry
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{fs, os::unix::fs::PermissionsExt};
+
+  use super::InputHistory;
+
+  #[test]
+  fn history_file_is_private_and_keeps_multi_line_entries() {
+    let dir = std::env::temp_dir().join(format!("tchat-history-{}", std::process::id()));
+    fs::remove_dir_all(&dir).ok();
+    let file = dir.join("input_history");
+
+    let mut history = InputHistory::open(file.clone()).unwrap();
+    history.push("first\nsecond \\n").unwrap();
+    assert_eq!(fs::metadata(&file).unwrap().permissions().mode() & 0o777, 0o600);
+
+    let mut history = InputHistory::open(file).unwrap();
+    assert_eq!(history.older(""), Some("first\nsecond \\n"));
+
+    fs::remove_dir_all(&dir).ok();
+  }
+}
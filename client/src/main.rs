@@ -1,4 +1,4 @@
-use std::io;
+use std::{io, process};
 
 use service::Service;
 
@@ -8,15 +8,24 @@ use crate::{
 };
 
 mod settings;
-mod types;
+mod errors;
 mod connection;
 mod state;
+mod editor;
+mod history;
+mod commands;
+mod message;
+mod render;
+mod wrap;
 mod service;
 
 fn main() -> io::Result<()> {
   let settings = Settings::new();
-  let state = State::new()?;
+  let state = State::new(&settings)?;
   
-  Service::run(settings, state)?;
+  if let Err(e) = Service::run(settings, state) {
+    eprintln!("{e}");
+    process::exit(1);
+  }
   Ok(())
-}
\ No newline at end of file
+}
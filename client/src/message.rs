@@ -0,0 +1,279 @@
+use chrono::{
+  format::{Item, StrftimeItems},
+  DateTime,
+  Local
+};
+
+use tchat_protocol::{PresenceEvent, SignalData, SignalType, DEFAULT_ROOM};
+
+use crate::wrap::{display_width, wrap};
+
+pub const DEFAULT_TEMPLATE: &str = "[{time}] [{room}] <{author}> {body}";
+pub const DEFAULT_TIME_FORMAT: &str = "%H:%M";
+/// Начало текста `/me`, как CTCP ACTION в IRC
+pub const ACTION_PREFIX: &str = "\x01ACTION ";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MessageKind {
+  Chat,
+  /// `/me`, в тексте только действие без ACTION_PREFIX
+  Action,
+  /// Уведомления сервера, например о пропусках в истории
+  Server,
+  /// Кто-то вошел, вышел, сменил имя или был отключен
+  Presence(PresenceEvent),
+  Direct { target: String },
+  Error,
+  /// Ответы на команды, например список комнат
+  Info,
+}
+
+/// Одна строка чата. Хранится данными, чтобы ее можно было отрисовать заново в
+/// другом формате
+#[derive(Debug, Clone)]
+pub struct Message {
+  pub time: Option<DateTime<Local>>,
+  pub room: String,
+  pub author: String,
+  pub body: String,
+  pub kind: MessageKind,
+}
+
+/// Как выглядят строки чата. В `template` можно использовать `{time}`,
+/// `{room}`, `{author}` и `{body}`, `time_format` - строка strftime
+#[derive(Debug, Clone)]
+pub struct LineFormat {
+  pub template: String,
+  pub time_format: String,
+}
+
+/// Проверяет строку strftime, чтобы отрисовка потом не падала
+pub fn parse_time_format(v: &str) -> Result<String, String> {
+  if StrftimeItems::new(v).any(|item| item == Item::Error) {
+    return Err(format!("invalid time format: {v}"));
+  }
+  Ok(v.to_owned())
+}
+
+/// Текст для PRESENCE сигнала
+fn presence_text(s: &SignalData) -> Option<String> {
+  let username = s.username.as_deref().unwrap_or_default();
+  let room = s.room.as_deref().unwrap_or(DEFAULT_ROOM);
+  let text = match (s.event?, room) {
+    (PresenceEvent::Joined, DEFAULT_ROOM) => format!("{username} joined the chat!"),
+    (PresenceEvent::Joined, _) => format!("{username} joined {room}"),
+    (PresenceEvent::Left, DEFAULT_ROOM) => format!("{username} left the chat!"),
+    (PresenceEvent::Left, _) => format!("{username} left {room}"),
+    (PresenceEvent::Renamed, _) => format!("{username} is now known as {}", s.new_username.as_deref()?),
+    (PresenceEvent::Kicked, _) => format!("{username} was disconnected by the server")
+  };
+  Some(match &s.reason {
+    Some(reason) => format!("{text} ({reason})"),
+    None => text
+  })
+}
+
+/// Убирает управляющие символы, кроме перевода строки. Табуляция
+/// становится пробелом, ее ширину на экране не посчитать
+fn printable(text: &str) -> String {
+  text.chars()
+    .filter_map(|c| match c {
+      '\n' => Some(c),
+      '\t' => Some(' '),
+      _ if c.is_control() => None,
+      _ => Some(c)
+    })
+    .collect()
+}
+
+impl Message {
+  /// Сигналы, которые не показываются в чате, дают None
+  pub fn from_signal(mut s: SignalData) -> Option<Message> {
+    // Чужой текст не должен управлять терминалом, например чистить экран
+    // или сбивать scroll region. ACTION_PREFIX сам начинается с \x01
+    s.message = s.message.map(|v| match v.strip_prefix(ACTION_PREFIX) {
+      Some(v) => format!("{ACTION_PREFIX}{}", printable(v)),
+      None => printable(&v)
+    });
+    for v in [&mut s.username, &mut s.target, &mut s.room, &mut s.new_username, &mut s.reason].into_iter().flatten() {
+      *v = printable(v);
+    }
+
+    let kind = match s.signal_type? {
+      SignalType::NewMessage | SignalType::History if s.server_message => MessageKind::Server,
+      SignalType::NewMessage | SignalType::History => match &s.message {
+        Some(v) if v.starts_with(ACTION_PREFIX) => MessageKind::Action,
+        _ => MessageKind::Chat
+      },
+      SignalType::DirectMessage => MessageKind::Direct { target: s.target.unwrap_or_default() },
+      SignalType::Error => MessageKind::Error,
+      SignalType::Presence => {
+        s.message = Some(presence_text(&s)?);
+        MessageKind::Presence(s.event?)
+      },
+      SignalType::List => {
+        // Каждая строка - "<комната> <участников>"
+        let rooms = s.message.unwrap_or_default()
+          .lines()
+          .filter_map(|v| v.split_once(' '))
+          .map(|(name, members)| format!("{name} ({members})"))
+          .collect::<Vec<String>>()
+          .join(", ");
+        return Some(Message::info(&format!("Rooms: {rooms}")))
+      },
+      _ => return None
+    };
+
+    Some(Message {
+      time: s.timestamp
+        .and_then(|v| DateTime::from_timestamp(v as i64, 0))
+        .map(|v| v.with_timezone(&Local)),
+      room: s.room.unwrap_or(DEFAULT_ROOM.to_owned()),
+      author: s.username.unwrap_or_default(),
+      body: match kind {
+        MessageKind::Action => s.message.unwrap_or_default()[ACTION_PREFIX.len()..].to_owned(),
+        _ => s.message.unwrap_or_default()
+      },
+      kind
+    })
+  }
+
+  /// Входы и выходы, которые можно скрыть настройкой
+  pub fn is_join_or_part(&self) -> bool {
+    matches!(self.kind, MessageKind::Presence(PresenceEvent::Joined | PresenceEvent::Left))
+  }
+
+  pub fn info(body: &str) -> Message {
+    Message::local(body, MessageKind::Info)
+  }
+
+  pub fn error(body: &str) -> Message {
+    Message::local(body, MessageKind::Error)
+  }
+
+  /// Сообщение клиента самому себе, на сервер не уходит
+  fn local(body: &str, kind: MessageKind) -> Message {
+    Message {
+      time: Some(Local::now()),
+      room: String::new(),
+      author: String::new(),
+      body: body.to_owned(),
+      kind
+    }
+  }
+
+  /// Строки для показа сообщения, перенесенные по ширине `width`. Продолжения
+  /// выровнены по началу текста сообщения
+  pub fn render(&self, format: &LineFormat, width: usize) -> Vec<String> {
+    let (style, text, indent) = match &self.kind {
+      MessageKind::Chat => {
+        let (text, indent) = self.fill(format, &self.room, &self.author, &self.body);
+        (String::new(), text, indent)
+      },
+      MessageKind::Action => {
+        let body = format!("{} {}", self.author, self.body);
+        let (text, indent) = self.fill(format, &self.room, "*", &body);
+        (termion::style::Italic.to_string(), text, indent)
+      },
+      MessageKind::Server => {
+        let (text, indent) = self.fill(format, &self.room, "*", &self.body);
+        (format!("{}{}", termion::style::Faint, termion::style::Bold), text, indent)
+      },
+      MessageKind::Presence(event) => {
+        let (text, indent) = self.fill(format, &self.room, "*", &self.body);
+        let style = match event {
+          PresenceEvent::Joined => termion::color::Fg(termion::color::Green).to_string(),
+          PresenceEvent::Left => termion::color::Fg(termion::color::Yellow).to_string(),
+          PresenceEvent::Renamed => termion::color::Fg(termion::color::Cyan).to_string(),
+          PresenceEvent::Kicked => termion::color::Fg(termion::color::Red).to_string()
+        };
+        (style, text, indent)
+      },
+      MessageKind::Direct { target } => {
+        let (text, indent) = self.fill(format, "DM", &format!("{} -> {target}", self.author), &self.body);
+        (termion::color::Fg(termion::color::Magenta).to_string(), text, indent)
+      },
+      MessageKind::Error => (termion::color::Fg(termion::color::Red).to_string(), self.body.clone(), 0),
+      MessageKind::Info => (termion::style::Faint.to_string(), self.body.clone(), 0)
+    };
+
+    text.split('\n')
+      .enumerate()
+      .flat_map(|(index, line)| match index {
+        0 => wrap(line, width, indent),
+        _ => wrap(&(" ".repeat(indent) + line), width, indent)
+      })
+      // Стиль у каждой строки свой, они рисуются по отдельности
+      .map(|line| match style.is_empty() {
+        true => line,
+        false => format!("{style}{line}{}", termion::style::Reset)
+      })
+      .collect()
+  }
+
+  /// Подставляет поля в шаблон за один проход, чтобы `{...}` в тексте
+  /// сообщения не подменялись. Возвращает строку и ширину того, что стоит
+  /// перед текстом сообщения.
+  fn fill(&self, format: &LineFormat, room: &str, author: &str, body: &str) -> (String, usize) {
+    let time = match self.time {
+      Some(v) => v.format(&format.time_format).to_string(),
+      None => "--:--".to_owned()
+    };
+
+    let mut line = String::new();
+    let mut indent = 0;
+    let mut rest = format.template.as_str();
+    while let Some(start) = rest.find('{') {
+      line.push_str(&rest[..start]);
+      rest = &rest[start..];
+      let Some(end) = rest.find('}') else {
+        break
+      };
+      match &rest[1..end] {
+        "time" => line.push_str(&time),
+        "room" => line.push_str(room),
+        "author" => line.push_str(author),
+        "body" => {
+          indent = display_width(&line);
+          line.push_str(body);
+        },
+        _ => line.push_str(&rest[..=end])
+      }
+      rest = &rest[end + 1..];
+    }
+    line.push_str(rest);
+    (line, indent)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use tchat_protocol::{SignalData, SignalHeader, SignalType};
+
+  use super::{Message, MessageKind, ACTION_PREFIX};
+
+  fn chat(username: &str, text: &str) -> SignalData {
+    SignalData::new(
+      vec![
+        SignalHeader::SignalType(SignalType::NewMessage),
+        SignalHeader::Username(username.to_owned()),
+        SignalHeader::WithMessage
+      ],
+      Some(text)
+    )
+  }
+
+  #[test]
+  fn escape_sequences_are_not_passed_to_the_terminal() {
+    let message = Message::from_signal(chat("ivan\x1b[2J", "hi\x1b[r\x1b[2J\r\nthere\u{9b}\tyou")).unwrap();
+    assert_eq!(message.author, "ivan[2J");
+    assert_eq!(message.body, "hi[r[2J\nthere you");
+  }
+
+  #[test]
+  fn actions_keep_their_prefix_through_cleaning() {
+    let message = Message::from_signal(chat("ivan", &format!("{ACTION_PREFIX}waves\x07"))).unwrap();
+    assert!(matches!(message.kind, MessageKind::Action));
+    assert_eq!(message.body, "waves");
+  }
+}
@@ -0,0 +1,302 @@
+use std::{
+  io::{self, Stdout, Write},
+  ops::Range,
+  sync::Arc
+};
+use parking_lot::Mutex;
+use termion::{
+  clear,
+  color,
+  cursor::{self, Goto},
+  input::MouseTerminal,
+  raw::{IntoRawMode, RawTerminal},
+  screen::{AlternateScreen, IntoAlternateScreen},
+  style
+};
+
+use crate::{
+  editor::LineEditor,
+  message::{LineFormat, Message},
+  state::{Members, Viewport},
+  wrap::{display_width, head, tail}
+};
+
+/// Больше строк ввод не занимает, остальные прокручиваются
+const MAX_INPUT_ROWS: usize = 5;
+/// Ширина списка участников вместе с разделителем
+const SIDEBAR_WIDTH: usize = 20;
+/// На более узком экране список участников не показывается
+const MIN_COLS_WITH_SIDEBAR: usize = 60;
+
+/// Raw mode, мышь и альтернативный экран, пока открыт чат. При удалении
+/// терминал восстанавливается
+pub struct Screen(MouseTerminal<AlternateScreen<RawTerminal<Stdout>>>);
+
+impl Screen {
+  pub fn new() -> io::Result<Screen> {
+    let stdout = io::stdout().into_raw_mode()?.into_alternate_screen()?;
+    Ok(Screen(MouseTerminal::from(stdout)))
+  }
+}
+
+impl Drop for Screen {
+  fn drop(&mut self) {
+    // Scroll region сбрасываем сами, остальное вернут обертки
+    write!(self.0, "\x1b[r{}", cursor::Show).ok();
+    self.0.flush().ok();
+  }
+}
+
+/// Что изменилось с прошлой отрисовки
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Redraw {
+  /// Только строка ввода
+  Input,
+  /// Новые сообщения или прокрутка
+  Messages,
+  /// Весь экран, например в первый раз
+  All,
+}
+
+/// То, что сейчас нарисовано в области сообщений
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Frame {
+  cols: u16,
+  rows: u16,
+  height: usize,
+  total: usize,
+  offset: usize,
+  new_below: bool,
+}
+
+/// Рисует чат без очистки экрана: набор текста перерисовывает строку ввода,
+/// новые сообщения внизу дописываются прокруткой области сообщений, остальное
+/// перерисовывается на месте
+pub struct Renderer {
+  username: Arc<Mutex<String>>,
+  room: Arc<Mutex<String>>,
+  messages: Arc<Mutex<Vec<Message>>>,
+  user_input: Arc<Mutex<LineEditor>>,
+  viewport: Arc<Mutex<Viewport>>,
+  members: Arc<Mutex<Members>>,
+  line_format: LineFormat,
+  /// Уже отрисованные в строки сообщения, перенесенные по ширине `width`
+  lines: Vec<String>,
+  rendered_messages: usize,
+  width: usize,
+  frame: Option<Frame>,
+  /// Строк внизу экрана под ввод
+  input_rows: u16,
+}
+
+impl Renderer {
+  pub fn new(
+    username: Arc<Mutex<String>>,
+    room: Arc<Mutex<String>>,
+    messages: Arc<Mutex<Vec<Message>>>,
+    user_input: Arc<Mutex<LineEditor>>,
+    viewport: Arc<Mutex<Viewport>>,
+    members: Arc<Mutex<Members>>,
+    line_format: LineFormat
+  ) -> Renderer {
+    Renderer {
+      username,
+      room,
+      messages,
+      user_input,
+      viewport,
+      members,
+      line_format,
+      lines: Vec::new(),
+      rendered_messages: 0,
+      width: 0,
+      frame: None,
+      input_rows: 1
+    }
+  }
+
+  pub fn draw(&mut self, redraw: Redraw) -> io::Result<()> {
+    let mut out = io::stdout().lock();
+    let (_, rows) = termion::terminal_size().unwrap_or((80, 24));
+    let lines = self.user_input.lock().text().split('\n').count();
+    let input_rows = lines.min(MAX_INPUT_ROWS).min(rows as usize / 2).max(1) as u16;
+    // Ввод вырос или уменьшился, сообщениям досталось другое число строк
+    let resized = input_rows != self.input_rows;
+    self.input_rows = input_rows;
+
+    if redraw >= Redraw::Messages || self.frame.is_none() || resized {
+      self.draw_messages(&mut out, redraw == Redraw::All)?;
+    }
+    self.draw_input(&mut out)?;
+    out.flush()
+  }
+
+  fn draw_messages(&mut self, out: &mut impl Write, force: bool) -> io::Result<()> {
+    let (cols, rows) = termion::terminal_size().unwrap_or((80, 24));
+    let sidebar = match self.viewport.lock().sidebar && cols as usize >= MIN_COLS_WITH_SIDEBAR {
+      true => SIDEBAR_WIDTH,
+      false => 0
+    };
+    // При новой ширине переносим все сообщения заново
+    let messages = self.messages.lock();
+    // После /clear сообщений меньше, чем уже отрисовано
+    let width = cols as usize - sidebar;
+    let rewrapped = width != self.width || messages.len() < self.rendered_messages;
+    if rewrapped {
+      self.width = width;
+      self.lines.clear();
+      self.rendered_messages = 0;
+    }
+    for m in &messages[self.rendered_messages..] {
+      self.lines.extend(m.render(&self.line_format, self.width));
+      self.rendered_messages += 1;
+    }
+    drop(messages);
+
+    // Нижние строки экрана под ввод
+    let mut height = rows.saturating_sub(self.input_rows) as usize;
+    let mut viewport = self.viewport.lock();
+    if rewrapped {
+      // Строк стало больше или меньше из-за переноса, а не из-за новых сообщений
+      viewport.total_lines = self.lines.len();
+    }
+    let mut visible = viewport.layout(self.lines.len(), height);
+    if viewport.new_below {
+      height = height.saturating_sub(1);
+      visible = viewport.layout(self.lines.len(), height);
+    }
+    let frame = Frame {
+      cols,
+      rows,
+      height,
+      total: self.lines.len(),
+      offset: viewport.offset,
+      new_below: viewport.new_below
+    };
+    drop(viewport);
+
+    let previous = self.frame.replace(frame);
+    match previous {
+      Some(v) if !force && !rewrapped && v.offset == 0 && frame.offset == 0 && v.cols == cols && v.rows == rows && v.height == height => {
+        let appended = frame.total.saturating_sub(v.total);
+        // Область сообщений ограничена scroll region, поэтому перевод строки
+        // внизу сдвигает только ее, а строка ввода остается на месте
+        for line in &self.lines[visible.end - appended.min(height)..visible.end] {
+          write!(out, "{}\r\n{}{}", Goto(1, height as u16), clear::CurrentLine, line)?;
+        }
+      },
+      _ => self.draw_all(out, &visible, frame)?
+    }
+
+    // Список участников сдвигается вместе с сообщениями, поэтому рисуется
+    // заново каждый раз
+    if sidebar > 0 {
+      self.draw_sidebar(out, cols, rows.saturating_sub(self.input_rows))?;
+    }
+    Ok(())
+  }
+
+  fn draw_sidebar(&self, out: &mut impl Write, cols: u16, rows: u16) -> io::Result<()> {
+    let room = self.room.lock().clone();
+    let members = self.members.lock();
+    let users = members.of(&room);
+    let left = cols - SIDEBAR_WIDTH as u16 + 1;
+    let width = SIDEBAR_WIDTH - 2;
+
+    for row in 0..rows {
+      write!(out, "{}{}│{} {}", Goto(left, row + 1), style::Faint, style::Reset, clear::UntilNewline)?;
+      let index = row as usize;
+      // Первая строка - заголовок, последняя - сколько не поместилось
+      let text = match index {
+        0 => format!("{}{}{}", style::Bold, head(&format!("{room} ({})", users.len()), width), style::Reset),
+        _ if index == rows as usize - 1 && users.len() > index => format!("+{} more", users.len() - index + 1),
+        _ => match users.get(index - 1) {
+          Some(v) => head(v, width).to_owned(),
+          None => continue
+        }
+      };
+      write!(out, "{text}")?;
+    }
+    Ok(())
+  }
+
+  fn draw_all(&self, out: &mut impl Write, visible: &Range<usize>, frame: Frame) -> io::Result<()> {
+    write!(out, "\x1b[1;{}r", frame.height.max(1))?;
+
+    // Сообщения прижаты к низу, как в обычном чате
+    let lines = &self.lines[visible.clone()];
+    let top = frame.height - lines.len();
+    for row in 0..frame.height {
+      write!(out, "{}{}", Goto(1, row as u16 + 1), clear::CurrentLine)?;
+      if row >= top {
+        write!(out, "{}", lines[row - top])?;
+      }
+    }
+
+    let indicator_row = frame.height as u16 + 1;
+    if frame.new_below {
+      write!(
+        out,
+        "{}{}{}{} new messages below {}",
+        Goto(1, indicator_row),
+        clear::CurrentLine,
+        color::Bg(color::Yellow),
+        color::Fg(color::Black),
+        style::Reset
+      )?;
+    }
+    Ok(())
+  }
+
+  fn draw_input(&self, out: &mut impl Write) -> io::Result<()> {
+    let (cols, rows) = termion::terminal_size().unwrap_or((80, 24));
+    let username = self.username.lock().clone();
+    let input = self.user_input.lock();
+    let prompt = match input.search() {
+      Some(v) if !v.query.is_empty() && v.found.is_none() => format!("(failed reverse-i-search)'{}' >", v.query),
+      Some(v) => format!("(reverse-i-search)'{}' >", v.query),
+      None => format!("{username} {} >", self.room.lock())
+    };
+    let prompt_width = display_width(&prompt);
+    let available = (cols as usize).saturating_sub(prompt_width + 2);
+
+    let (text, cursor) = (input.text(), input.cursor());
+    let lines: Vec<&str> = text.split('\n').collect();
+    let cursor_line = text[..cursor].matches('\n').count();
+    let cursor_column = cursor - text[..cursor].rfind('\n').map_or(0, |v| v + 1);
+    // Если строк больше, чем помещается, показываем те, что до курсора
+    let first = (cursor_line + 1).saturating_sub(self.input_rows as usize);
+    let top = rows.saturating_sub(self.input_rows) + 1;
+    let mut cursor_at = Goto(prompt_width as u16 + 2, top);
+
+    for row in 0..self.input_rows {
+      write!(out, "{}{}", Goto(1, top + row), clear::CurrentLine)?;
+      match row {
+        0 => write!(out, "{}{}{}{} ", color::Bg(color::White), color::Fg(color::Black), prompt, style::Reset)?,
+        // Продолжение выровнено по началу текста после подсказки
+        _ => write!(out, "{}", " ".repeat(prompt_width + 1))?
+      }
+
+      let index = first + row as usize;
+      let Some(line) = lines.get(index) else {
+        continue
+      };
+      if index != cursor_line {
+        write!(out, "{}", head(line, available))?;
+        continue;
+      }
+
+      // Длинную строку не переносим, чтобы ввод не залезал на сообщения,
+      // а сдвигаем так, чтобы курсор оставался виден
+      let (before, after) = line.split_at(cursor_column);
+      let before = match display_width(before) < available {
+        true => before,
+        false => tail(before, available.saturating_sub(1))
+      };
+      let after = head(after, available - display_width(before));
+      write!(out, "{before}{after}")?;
+      cursor_at = Goto((prompt_width + 2 + display_width(before)) as u16, top + row);
+    }
+    write!(out, "{cursor_at}")
+  }
+}
@@ -1,26 +1,39 @@
 use std::{
   thread, 
-  io::{
-    self, 
-    Write
-  },
+  io,
   str::FromStr
 };
+use signal_hook::{consts::SIGWINCH, iterator::Signals};
 use termion::{
-  raw::IntoRawMode, 
-  input::TermRead
+  input::TermRead,
+  event::{Event, Key, MouseButton, MouseEvent}
 };
 use crate::{
   settings::Settings, 
   state::State, 
-  connection::Connection, 
-  types::{
-    SignalType, 
-    SignalData, 
-    SignalHeader
-  }
+  connection::Connection,
+  errors::{CommandError, ConnectionError},
+  commands::{self, Action, Context, Input},
+  message::Message,
+  render::{Redraw, Renderer, Screen}
+};
+use tchat_protocol::{
+  PresenceEvent,
+  SignalData, 
+  MAX_CONTENT_LENGTH,
+  DEFAULT_ROOM
 };
 
+/// На столько строк прокручивает колесо мыши
+const WHEEL_LINES: usize = 3;
+
+/// Страница прокрутки: высота чата без строки ввода и без одной строки,
+/// которая остается на экране для контекста
+fn page_size() -> usize {
+  let (_, rows) = termion::terminal_size().unwrap_or((80, 24));
+  rows.saturating_sub(2).max(1) as usize
+}
+
 pub struct Service {
   pub connection: Connection,
   pub settings: Settings,
@@ -28,12 +41,15 @@ pub struct Service {
 }
 
 impl Service {
-  pub fn run(settings: Settings, state: State) -> io::Result<()> {
+  pub fn run(settings: Settings, state: State) -> Result<(), ConnectionError> {
     let connection = Connection::new(
       &settings.server_address.to_owned(), 
-      &state.username
+      &state.username.lock(),
+      settings.server_key.as_deref(),
+      commands::history_headers(settings.history, settings.history_since)
     )?;
 
+    let _screen = Screen::new()?;
     let mut instance = Service {
       connection,
       settings,
@@ -41,6 +57,8 @@ impl Service {
     }.enable_print();
 
     instance.proccess_incoming_messages();
+    instance.connection.send_signal(&commands::user_list(DEFAULT_ROOM))?;
+    instance.watch_resize()?;
     instance.read_inputs();
 
     Ok(())
@@ -48,82 +66,75 @@ impl Service {
 
   pub fn proccess_incoming_messages(&self) {
     let messages = self.state.messages.clone();
+    let members = self.state.members.clone();
+    let last_seen = self.state.last_seen.clone();
+    let username = self.state.username.clone();
+    let hide_joins = self.settings.hide_joins;
     let tx = self.state.chat_reload_sender.clone();
     let mut connection = self.connection.clone();
     thread::spawn(move || -> io::Result<()> {
-      loop {
-        let data_from_socket = match connection.read_signal() {
-          Ok(v) => v,
-          Err(_) => break
-        };
-        let signal = SignalData::from_str(&data_from_socket);
-        let mut messages = messages.lock();
-        if let Ok(s) = signal {
-          if let Some(SignalType::NewMessage) = s.signal_type {
-            if s.server_message {
-              messages.push(
-                format!(
-                  "{}{}{}{}",
-                  termion::style::Faint,
-                  termion::style::Bold,
-                  s.message.unwrap(),
-                  termion::style::Reset,
-                )
-              );
-            }
-            else {
-              messages.push(
-                format!(
-                  "<{}> {}", 
-                  s.username.unwrap(), 
-                  s.message.unwrap()
-                )
-              );
+      while let Ok(data_from_socket) = connection.read_signal() {
+        if let Ok(signal) = SignalData::from_str(&data_from_socket) {
+          members.lock().apply(&signal);
+          last_seen.lock().apply(&signal);
+          // Сервер подтвердил /nick, дальше сообщения уходят под новым именем.
+          // Переименование приходит в каждую комнату, меняем имя по первому
+          if let (Some(PresenceEvent::Renamed), Some(old), Some(new)) = (signal.event, &signal.username, &signal.new_username) {
+            let mut username = username.lock();
+            if *username == *old {
+              *username = new.clone();
             }
           }
+          match Message::from_signal(signal) {
+            Some(v) if hide_joins && v.is_join_or_part() => {},
+            Some(v) => messages.lock().push(v),
+            None => {}
+          }
         }
-        match tx.send(()) {
+        match tx.send(Redraw::Messages) {
           Ok(_) => {},
-          Err(_) => break
+          Err(_) => return Ok(())
         };
       }
-  
+
+      // Сервер закрыл соединение. Экран остается, чтобы было видно почему
+      messages.lock().push(Message::error("Connection closed by the server, press Ctrl-C to quit"));
+      tx.send(Redraw::Messages).ok();
       Ok(())
     });
   }
 
+  /// Перерисовывает экран целиком, когда меняется размер терминала
+  pub fn watch_resize(&self) -> io::Result<()> {
+    let mut signals = Signals::new([SIGWINCH])?;
+    let tx = self.state.chat_reload_sender.clone();
+    thread::spawn(move || {
+      for _ in signals.forever() {
+        if tx.send(Redraw::All).is_err() {
+          break;
+        }
+      }
+    });
+    Ok(())
+  }
+
   pub fn enable_print(self) -> Service {
     let rx = self.state.chat_reload_receiver.unwrap();
-    let messages = self.state.messages.clone();
-    let user_input = self.state.user_input.clone();
-    let username = self.state.username.clone();
+    let mut renderer = Renderer::new(
+      self.state.username.clone(),
+      self.state.room.clone(),
+      self.state.messages.clone(),
+      self.state.user_input.clone(),
+      self.state.viewport.clone(),
+      self.state.members.clone(),
+      self.settings.line_format.clone()
+    );
 
     thread::spawn(move || -> io::Result<()> {
-      loop {
-        match rx.recv() {
-          Ok(()) => {},
-          Err(_) => break
-        };
-        print!("{}", termion::clear::All);
-        for (index, m) in messages.lock().iter().enumerate() {
-          if index == 0 {
-            print!("\r\n{m}\r\n");
-          }
-          else {
-            print!("{m}\r\n");
-          }
-        }
-        let input = user_input.lock().clone();
-        print!(
-          "{}{}{} >{} {}", 
-          termion::color::Bg(termion::color::White), 
-          termion::color::Fg(termion::color::Black), 
-          username, 
-          termion::style::Reset,
-          input
-        );
-
-        std::io::stdout().flush()?;
+      while let Ok(redraw) = rx.recv() {
+        // Накопившиеся запросы рисуем за один раз
+        let redraw = rx.try_iter().fold(redraw, |a, b| a.max(b));
+        renderer.draw(redraw)?;
       }
       Ok(())
     });
@@ -137,58 +148,136 @@ impl Service {
         chat_reload_sender: self.state.chat_reload_sender.clone(),
         user_input: self.state.user_input.clone(),
         messages: self.state.messages.clone(),
+        room: self.state.room.clone(),
+        viewport: self.state.viewport.clone(),
+        members: self.state.members.clone(),
+        last_seen: self.state.last_seen.clone(),
       }
     }
   }
 
+  /// Что сделать с набранной строкой: команду выполняем, текст
+  /// отправляем в текущую комнату. Err - ошибка, которую видит только
+  /// сам пользователь.
+  fn action(&self, input: &str) -> Result<Action, String> {
+    let context = Context {
+      room: &self.state.room,
+      history: self.settings.history,
+      history_since: self.settings.history_since,
+      members: &self.state.members,
+      last_seen: &self.state.last_seen
+    };
+
+    match commands::parse(input) {
+      Input::Text(v) => Ok(Action::Send(vec![commands::chat_message(&context, v)])),
+      Input::Command(command, arguments) => match command.run(&context, arguments) {
+        Ok(v) => Ok(v),
+        Err(CommandError::Usage) => Err(format!("Usage: /{} {}", command.name, command.usage)),
+        Err(e) => Err(e.to_string())
+      },
+      Input::Unknown(name) => Err(format!("Unknown command /{name}, see /help"))
+    }
+  }
+
   pub fn read_inputs(&mut self) {
-    let stdout = io::stdout().into_raw_mode().unwrap(); // НЕЛЬЗЯ УБИРАТЬ
-    let mut stdin = io::stdin().keys();
+    self.state.chat_reload_sender.send(Redraw::All).ok();
+    let mut stdin = io::stdin().events();
 
     loop {
       let input = stdin.next();
   
-      if let Some(Ok(key)) = input {
-        match key {
-          termion::event::Key::Ctrl('c') => break,
-          termion::event::Key::Char('\n') => {
-            let ms = self.state.user_input.lock().clone().trim().to_owned();
-            if ms == "" {
-              match self.state.chat_reload_sender.send(()) {
+      if let Some(Ok(event)) = input {
+        match event {
+          Event::Key(Key::Ctrl('c')) => break,
+          Event::Key(Key::F(2)) => {
+            self.state.viewport.lock().toggle_sidebar();
+            match self.state.chat_reload_sender.send(Redraw::Messages) {
+              Ok(_) => {},
+              Err(_) => break, 
+            };
+          },
+          Event::Key(Key::PageUp) => {
+            self.state.viewport.lock().scroll_up(page_size());
+            match self.state.chat_reload_sender.send(Redraw::Messages) {
+              Ok(_) => {},
+              Err(_) => break, 
+            };
+          },
+          Event::Key(Key::PageDown) => {
+            self.state.viewport.lock().scroll_down(page_size());
+            match self.state.chat_reload_sender.send(Redraw::Messages) {
+              Ok(_) => {},
+              Err(_) => break, 
+            };
+          },
+          Event::Mouse(MouseEvent::Press(MouseButton::WheelUp, _, _)) => {
+            self.state.viewport.lock().scroll_up(WHEEL_LINES);
+            match self.state.chat_reload_sender.send(Redraw::Messages) {
+              Ok(_) => {},
+              Err(_) => break, 
+            };
+          },
+          Event::Mouse(MouseEvent::Press(MouseButton::WheelDown, _, _)) => {
+            self.state.viewport.lock().scroll_down(WHEEL_LINES);
+            match self.state.chat_reload_sender.send(Redraw::Messages) {
+              Ok(_) => {},
+              Err(_) => break, 
+            };
+          },
+          Event::Key(Key::Char('\n')) => {
+            let ms = self.state.user_input.lock().text().trim().to_owned();
+            if ms.is_empty() {
+              match self.state.chat_reload_sender.send(Redraw::Input) {
                 Ok(_) => {},
                 Err(_) => break, 
               };
               continue;
             }
-            self.state.user_input.lock().clear();
-            let signal = SignalData::new(
-              vec![
-                SignalHeader::SignalType(SignalType::NewMessage),
-                SignalHeader::WithMessage,
-                SignalHeader::Username(self.state.username.to_owned())
-              ],
-              Some(&ms)
-            );
-  
-            self.connection.stream.write_all(signal.to_string().as_bytes()).unwrap();
-          },
-          termion::event::Key::Backspace => {
-            self.state.user_input.lock().pop();
-            match self.state.chat_reload_sender.send(()) {
+            // Текст остается в поле ввода, чтобы его можно было сократить
+            if ms.len() > MAX_CONTENT_LENGTH {
+              let error = format!("Message too long ({} > {MAX_CONTENT_LENGTH} bytes)", ms.len());
+              self.state.messages.lock().push(Message::error(&error));
+              self.state.viewport.lock().scroll_down(usize::MAX);
+              match self.state.chat_reload_sender.send(Redraw::Messages) {
+                Ok(_) => {},
+                Err(_) => break, 
+              };
+              continue;
+            }
+            if let Err(e) = self.state.user_input.lock().submit() {
+              let info = Message::info(&format!("Input history is not saved: {e}"));
+              self.state.messages.lock().push(info);
+            }
+            match self.action(&ms) {
+              Ok(Action::Send(signals)) => {
+                for signal in signals {
+                  if let Err(e) = self.connection.send_signal(&signal) {
+                    self.state.messages.lock().push(Message::error(&format!("Not sent, the connection is lost: {e}")));
+                    break;
+                  }
+                }
+              },
+              Ok(Action::Info(text)) => self.state.messages.lock().push(Message::info(&text)),
+              Ok(Action::Clear) => self.state.messages.lock().clear(),
+              Ok(Action::ToggleSidebar) => self.state.viewport.lock().toggle_sidebar(),
+              Ok(Action::Quit) => break,
+              Err(e) => self.state.messages.lock().push(Message::error(&e))
+            }
+            // После отправки возвращаемся к последним сообщениям
+            self.state.viewport.lock().scroll_down(usize::MAX);
+            match self.state.chat_reload_sender.send(Redraw::Messages) {
               Ok(_) => {},
               Err(_) => break, 
             };
-          }
-          termion::event::Key::Char(k) => {
-            println!("{k}");
-            self.state.user_input.lock().push_str(&k.to_string());
-            match self.state.chat_reload_sender.send(()) {
+          },
+          event => {
+            if !self.state.user_input.lock().handle(&event) {
+              continue;
+            }
+            match self.state.chat_reload_sender.send(Redraw::Input) {
               Ok(_) => {},
               Err(_) => break, 
             };
-          },
-          _ => {
-            continue;
           }
         }
       }
@@ -1,5 +1,28 @@
+use std::{path::PathBuf, time::Duration};
+
 use clap::Parser;
 
+use tchat_protocol::DEFAULT_HISTORY_LIMIT;
+
+use crate::{
+  history,
+  message::{parse_time_format, LineFormat, DEFAULT_TEMPLATE, DEFAULT_TIME_FORMAT}
+};
+
+/// Читает возраст вроде `30m`, `2h` или `1d`
+fn parse_age(v: &str) -> Result<Duration, String> {
+  let invalid = || format!("invalid age: {v}, use minutes, hours or days like 30m, 2h or 1d");
+  let unit = v.chars().last().ok_or_else(invalid)?;
+  let number: u64 = v[..v.len() - unit.len_utf8()].parse().map_err(|_| invalid())?;
+  let seconds = match unit {
+    'm' => 60,
+    'h' => 60 * 60,
+    'd' => 24 * 60 * 60,
+    _ => return Err(invalid())
+  };
+  number.checked_mul(seconds).map(Duration::from_secs).ok_or_else(invalid)
+}
+
 #[derive(Parser)]
 pub struct Args {
   #[arg(short, long, help = "Server address")]
@@ -7,12 +30,37 @@ pub struct Args {
 
   #[arg(short, long, help = "Server secret key")]
   pub key: Option<String>,
+
+  #[arg(long, help = "How many recent messages to load when joining a room")]
+  pub history: Option<usize>,
+
+  #[arg(long, value_parser = parse_age, help = "Load only messages newer than this when joining a room, like 30m, 2h or 1d")]
+  pub history_since: Option<Duration>,
+
+  #[arg(long, help = "Chat line template, may use {time}, {room}, {author} and {body}")]
+  pub format: Option<String>,
+
+  #[arg(long, value_parser = parse_time_format, help = "strftime format of {time} in chat lines")]
+  pub time_format: Option<String>,
+
+  #[arg(long, help = "Keep sent messages for Up/Down and Ctrl-R between sessions")]
+  pub save_input: bool,
+
+  #[arg(long, help = "Don't show users joining and leaving rooms")]
+  pub hide_joins: bool,
 }
 
 #[derive(Debug, Clone)]
 pub struct Settings {
   pub server_address: String,
   pub server_key: Option<String>,
+  pub history: usize,
+  /// Насколько давние сообщения просить, None - без ограничения по времени
+  pub history_since: Option<Duration>,
+  pub line_format: LineFormat,
+  /// Файл истории ввода, None - история только на время сессии
+  pub input_history_file: Option<PathBuf>,
+  pub hide_joins: bool,
 }
 
 impl Settings {
@@ -21,7 +69,18 @@ impl Settings {
     
     Settings { 
       server_address: args.address,
-      server_key: args.key
+      server_key: args.key,
+      history: args.history.unwrap_or(DEFAULT_HISTORY_LIMIT),
+      history_since: args.history_since,
+      line_format: LineFormat {
+        template: args.format.unwrap_or(DEFAULT_TEMPLATE.to_owned()),
+        time_format: args.time_format.unwrap_or(DEFAULT_TIME_FORMAT.to_owned())
+      },
+      input_history_file: match args.save_input {
+        true => history::default_file(),
+        false => None
+      },
+      hide_joins: args.hide_joins
     }
   }
 }
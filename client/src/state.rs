@@ -1,4 +1,6 @@
 use std::{
+  collections::{BTreeSet, HashMap},
+  ops::Range,
   sync::{
     mpsc::{
       Sender, 
@@ -14,27 +16,195 @@ use std::{
 };
 
 use parking_lot::Mutex;
+use tchat_protocol::{PresenceEvent, SignalData, SignalType, DEFAULT_ROOM};
+
+use crate::{
+  editor::LineEditor,
+  history::InputHistory,
+  message::Message,
+  render::Redraw,
+  settings::Settings
+};
 
 pub struct State {
-  pub username: String,
-  pub chat_reload_receiver: Option<Receiver<()>>,
-  pub chat_reload_sender: Sender<()>,
-  pub user_input: Arc<Mutex<String>>,
-  pub messages: Arc<Mutex<Vec<String>>>
+  /// Меняется, когда сервер подтверждает /nick
+  pub username: Arc<Mutex<String>>,
+  pub chat_reload_receiver: Option<Receiver<Redraw>>,
+  pub chat_reload_sender: Sender<Redraw>,
+  pub user_input: Arc<Mutex<LineEditor>>,
+  pub messages: Arc<Mutex<Vec<Message>>>,
+  /// Комната, в которую уходят набранные сообщения
+  pub room: Arc<Mutex<String>>,
+  pub viewport: Arc<Mutex<Viewport>>,
+  pub members: Arc<Mutex<Members>>,
+  pub last_seen: Arc<Mutex<LastSeen>>
+}
+
+/// Какая часть чата сейчас на экране
+#[derive(Debug, Default)]
+pub struct Viewport {
+  /// На сколько строк чат прокручен вверх от последней
+  pub offset: usize,
+  /// Строк в чате при прошлой отрисовке
+  pub total_lines: usize,
+  /// Пока чат прокручен вверх, пришли новые сообщения
+  pub new_below: bool,
+  /// Справа показан список участников комнаты
+  pub sidebar: bool,
+}
+
+impl Viewport {
+  pub fn scroll_up(&mut self, lines: usize) {
+    // Ограничение сверху применится при отрисовке, там известна высота
+    self.offset += lines;
+  }
+
+  pub fn toggle_sidebar(&mut self) {
+    self.sidebar = !self.sidebar;
+  }
+
+  pub fn scroll_down(&mut self, lines: usize) {
+    self.offset = self.offset.saturating_sub(lines);
+    if self.offset == 0 {
+      self.new_below = false;
+    }
+  }
+
+  /// Возвращает строки из `total`, которые надо показать, если на экран
+  /// помещается `height`. Пока чат прокручен вверх, он стоит на месте, когда
+  /// приходят новые строки
+  pub fn layout(&mut self, total: usize, height: usize) -> Range<usize> {
+    if self.offset > 0 && total > self.total_lines {
+      self.offset += total - self.total_lines;
+      self.new_below = true;
+    }
+    self.total_lines = total;
+    self.offset = self.offset.min(total.saturating_sub(height));
+    if self.offset == 0 {
+      self.new_below = false;
+    }
+
+    let end = total - self.offset;
+    end.saturating_sub(height)..end
+  }
+}
+
+/// Кто есть в комнатах, где сидит пользователь. Обновляется ответами USER_LIST
+/// и сигналами PRESENCE
+#[derive(Debug, Default)]
+pub struct Members {
+  rooms: HashMap<String, BTreeSet<String>>,
+}
+
+impl Members {
+  pub fn apply(&mut self, signal: &SignalData) {
+    let Some(room) = &signal.room else {
+      return
+    };
+    match (signal.signal_type, signal.event) {
+      (Some(SignalType::UserList), _) => {
+        let users = signal.message.as_deref().unwrap_or_default()
+          .lines()
+          .filter(|v| !v.is_empty())
+          .map(|v| v.to_owned())
+          .collect();
+        self.rooms.insert(room.clone(), users);
+      },
+      // Комнату заводит только USER_LIST, до него изменения не нужны
+      (Some(SignalType::Presence), Some(event)) => {
+        let (Some(users), Some(username)) = (self.rooms.get_mut(room), &signal.username) else {
+          return
+        };
+        match event {
+          PresenceEvent::Joined => {
+            users.insert(username.clone());
+          },
+          PresenceEvent::Left | PresenceEvent::Kicked => {
+            users.remove(username);
+          },
+          PresenceEvent::Renamed => {
+            if let Some(v) = &signal.new_username {
+              users.remove(username);
+              users.insert(v.clone());
+            }
+          }
+        }
+      },
+      _ => {}
+    }
+  }
+
+  /// Есть ли пользователь в `room`, насколько известно клиенту
+  pub fn has(&self, room: &str) -> bool {
+    self.rooms.contains_key(room)
+  }
+
+  /// Забывает комнату, из которой пользователь вышел
+  pub fn forget(&mut self, room: &str) {
+    self.rooms.remove(room);
+  }
+
+  /// Имена в `room` по алфавиту
+  pub fn of(&self, room: &str) -> Vec<&str> {
+    self.rooms.get(room)
+      .map(|v| v.iter().map(|v| v.as_str()).collect())
+      .unwrap_or_default()
+  }
+}
+
+/// Номер последнего сообщения, полученного в каждой комнате. При повторном
+/// входе по нему просим только то, что пропустили
+#[derive(Debug, Default)]
+pub struct LastSeen {
+  rooms: HashMap<String, u64>,
+}
+
+impl LastSeen {
+  /// Сообщения приходят по порядку, поэтому просто запоминаем последний
+  /// номер. После сброса истории на сервере он станет меньше, так и надо
+  pub fn apply(&mut self, signal: &SignalData) {
+    let (Some(room), Some(seq)) = (&signal.room, signal.seq) else {
+      return
+    };
+    if matches!(signal.signal_type, Some(SignalType::NewMessage | SignalType::History)) {
+      self.rooms.insert(room.clone(), seq);
+    }
+  }
+
+  pub fn get(&self, room: &str) -> Option<u64> {
+    self.rooms.get(room).copied()
+  }
 }
 
 impl State {
-  pub fn new() -> io::Result<State> {
-    let (sx, rx) = mpsc::channel::<()>();
-    let user_input = Arc::new(Mutex::new(String::new()));
-    let messages = Arc::new(Mutex::new(Vec::<String>::new()));
+  pub fn new(settings: &Settings) -> io::Result<State> {
+    let (sx, rx) = mpsc::channel::<Redraw>();
+    let mut messages = Vec::<Message>::new();
+    // Без файла история просто не переживет сессию, это не повод не запускаться
+    let history = match &settings.input_history_file {
+      Some(path) => InputHistory::open(path.clone()).unwrap_or_else(|e| {
+        messages.push(Message::info(&format!("Can't load input history from {}: {e}", path.display())));
+        InputHistory::default()
+      }),
+      None => InputHistory::default()
+    };
+    let user_input = Arc::new(Mutex::new(LineEditor::new(history)));
+    let messages = Arc::new(Mutex::new(messages));
+    let room = Arc::new(Mutex::new(DEFAULT_ROOM.to_owned()));
+    let viewport = Arc::new(Mutex::new(Viewport::default()));
+    let members = Arc::new(Mutex::new(Members::default()));
+    let last_seen = Arc::new(Mutex::new(LastSeen::default()));
 
     let mut instance = State {
-      username: String::new(),
+      username: Arc::new(Mutex::new(String::new())),
       chat_reload_receiver: Some(rx),
       chat_reload_sender: sx,
       user_input,
       messages,
+      room,
+      viewport,
+      members,
+      last_seen,
     };
 
     instance.read_username()?;
@@ -50,9 +220,44 @@ impl State {
     let mut username = String::new();
     io::stdin().read_line(&mut username)?;
 
-    self.username = username.trim().to_owned();
+    *self.username.lock() = username.trim().to_owned();
     println!("{}", termion::clear::All);
 
     Ok(())
   }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+  use super::Viewport;
+
+  #[test]
+  fn layout_shows_the_last_lines() {
+    let mut viewport = Viewport::default();
+    assert_eq!(viewport.layout(100, 10), 90..100);
+    assert_eq!(viewport.layout(3, 10), 0..3);
+  }
+
+  #[test]
+  fn scrolled_chat_stays_in_place_as_lines_arrive() {
+    let mut viewport = Viewport::default();
+    viewport.layout(100, 10);
+    viewport.scroll_up(5);
+    assert_eq!(viewport.layout(100, 10), 85..95);
+
+    assert_eq!(viewport.layout(103, 10), 85..95);
+    assert!(viewport.new_below);
+
+    viewport.scroll_down(usize::MAX);
+    assert!(!viewport.new_below);
+    assert_eq!(viewport.layout(103, 10), 93..103);
+  }
+
+  #[test]
+  fn scrolling_stops_at_the_first_line() {
+    let mut viewport = Viewport::default();
+    viewport.layout(20, 10);
+    viewport.scroll_up(1000);
+    assert_eq!(viewport.layout(20, 10), 0..10);
+    assert_eq!(viewport.offset, 10);
+  }
+}
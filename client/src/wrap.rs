@@ -0,0 +1,124 @@
+use unicode_width::UnicodeWidthChar;
+
+/// Если отступ съедает почти всю ширину, переносим без него
+const MIN_TEXT_WIDTH: usize = 10;
+
+/// Сколько колонок `text` занимает на экране. Широкие символы вроде CJK и
+/// большинства эмодзи занимают две, управляющие - ни одной
+pub fn display_width(text: &str) -> usize {
+  text.chars().map(|c| c.width().unwrap_or(0)).sum()
+}
+
+/// Конец `text`, который помещается в `width` колонок
+pub fn tail(text: &str, width: usize) -> &str {
+  let mut used = 0;
+  for (index, c) in text.char_indices().rev() {
+    used += c.width().unwrap_or(0);
+    if used > width {
+      return &text[index + c.len_utf8()..];
+    }
+  }
+  text
+}
+
+/// Начало `text`, которое помещается в `width` колонок
+pub fn head(text: &str, width: usize) -> &str {
+  let mut used = 0;
+  for (index, c) in text.char_indices() {
+    used += c.width().unwrap_or(0);
+    if used > width {
+      return &text[..index];
+    }
+  }
+  text
+}
+
+/// Переносит одну строку по словам в `width` колонок. Продолжения начинаются с
+/// `indent` пробелов, слова длиннее строки разрываются
+pub fn wrap(line: &str, width: usize, indent: usize) -> Vec<String> {
+  if width == 0 {
+    return vec![line.to_owned()];
+  }
+  let indent = match indent + MIN_TEXT_WIDTH > width {
+    true => 0,
+    false => indent
+  };
+
+  let mut lines = Vec::new();
+  let mut current = String::new();
+  let mut current_width = 0;
+  // Где в текущей строке можно перенести: байт после пробела и ширина до него
+  let mut last_space: Option<(usize, usize)> = None;
+
+  for c in line.chars() {
+    let char_width = c.width().unwrap_or(0);
+    if current_width + char_width > width {
+      if c == ' ' {
+        // Пробел на месте переноса не нужен
+        lines.push(current);
+        current = " ".repeat(indent);
+        current_width = indent;
+        last_space = None;
+        continue;
+      }
+      match last_space {
+        Some((at, at_width)) => {
+          let tail = current.split_off(at);
+          lines.push(current.trim_end_matches(' ').to_owned());
+          current = " ".repeat(indent) + &tail;
+          current_width = indent + current_width - at_width;
+        },
+        None => {
+          lines.push(current);
+          current = " ".repeat(indent);
+          current_width = indent;
+        }
+      }
+      last_space = None;
+    }
+
+    current.push(c);
+    current_width += char_width;
+    if c == ' ' && current_width > indent {
+      last_space = Some((current.len(), current_width));
+    }
+  }
+  lines.push(current);
+  lines
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{display_width, head, tail, wrap};
+
+  #[test]
+  fn wide_characters_take_two_columns() {
+    assert_eq!(display_width("你好a😀"), 7);
+    assert_eq!(display_width("привет"), 6);
+    assert_eq!(head("你好世界", 5), "你好");
+    assert_eq!(tail("你好世界", 3), "界");
+    assert_eq!(head("abc", 10), "abc");
+  }
+
+  #[test]
+  fn lines_break_at_spaces_with_a_hanging_indent() {
+    assert_eq!(wrap("hello world foo", 11, 0), vec!["hello world", "foo"]);
+    assert_eq!(
+      wrap("ivan: alpha beta gamma delta", 20, 6),
+      vec!["ivan: alpha beta", "      gamma delta"]
+    );
+  }
+
+  #[test]
+  fn wide_characters_are_not_split_over_the_edge() {
+    assert_eq!(wrap("你好世界你好", 5, 0), vec!["你好", "世界", "你好"]);
+    for line in wrap("мама мыла раму 你好世界 😀😀😀", 7, 0) {
+      assert!(display_width(&line) <= 7, "{line}");
+    }
+  }
+
+  #[test]
+  fn long_words_are_broken() {
+    assert_eq!(wrap("abcdefghij", 4, 0), vec!["abcd", "efgh", "ij"]);
+  }
+}
@@ -0,0 +1,29 @@
+//! Формат сигналов, общий для клиента и сервера tchat
+
+mod types;
+mod reader;
+
+pub use types::{
+  PROTOCOL_VERSION,
+  MAX_USERNAME_LENGTH,
+  MAX_ROOM_NAME_LENGTH,
+  DEFAULT_ROOM,
+  DEFAULT_HISTORY_LIMIT,
+  is_valid_username,
+  is_valid_room_name,
+  ParseSignalDataError,
+  AuthConnectionError,
+  SignalType,
+  AuthStatus,
+  DenialReason,
+  PresenceEvent,
+  SignalHeader,
+  SignalData
+};
+pub use reader::{
+  SignalReader,
+  MAX_CONTENT_LENGTH,
+  MAX_HEADER_LENGTH
+};
+#[cfg(feature = "tokio")]
+pub use reader::AsyncSignalReader;
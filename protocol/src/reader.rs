@@ -0,0 +1,122 @@
+use std::{
+  io::{self, BufRead, Error, ErrorKind, Read},
+  str::FromStr
+};
+#[cfg(feature = "tokio")]
+use std::future::Future;
+#[cfg(feature = "tokio")]
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt};
+
+use crate::types::SignalHeader;
+
+/// Ограничение CONTENT_LENGTH, чтобы собеседник не заставил выделить сколько
+/// угодно памяти
+pub const MAX_CONTENT_LENGTH: usize = 64 * 1024;
+/// Ограничение на все строки хедеров одного сигнала вместе. Без него одна
+/// бесконечная строка без перевода строки копилась бы в памяти
+pub const MAX_HEADER_LENGTH: usize = 8 * 1024;
+
+pub trait SignalReader {
+  /// Читает ровно один сигнал: строки хедеров до пустой строки и потом
+  /// CONTENT_LENGTH байт сообщения, если такой хедер есть
+  fn read_signal(&mut self) -> io::Result<String>;
+}
+
+/// То же, что [`SignalReader`], но для потоков tokio
+#[cfg(feature = "tokio")]
+pub trait AsyncSignalReader {
+  fn read_signal(&mut self) -> impl Future<Output = io::Result<String>> + Send;
+}
+
+/// Возвращает true, когда хедеры закончились. Строки должны кончаться на
+/// `\r\n`, как их делит `SignalData::from_str`, иначе сигнал, прочитанный
+/// здесь, потом не разобрать
+fn read_header_line(line: &str, content_length: &mut usize) -> io::Result<bool> {
+  if line.ends_with('\n') && !line.ends_with("\r\n") {
+    return Err(Error::new(ErrorKind::InvalidData, "Header line must end with \\r\\n"));
+  }
+  if line == "\r\n" {
+    return Ok(true);
+  }
+  if let Ok(SignalHeader::ContentLength(v)) = SignalHeader::from_str(line.trim_end_matches("\r\n")) {
+    *content_length = v;
+  }
+  Ok(false)
+}
+
+fn check_content_length(content_length: usize) -> io::Result<()> {
+  if content_length > MAX_CONTENT_LENGTH {
+    return Err(Error::new(ErrorKind::InvalidData, "Message is too long"));
+  }
+  Ok(())
+}
+
+fn decode_body(body: Vec<u8>) -> io::Result<String> {
+  String::from_utf8(body)
+    .map_err(|_| Error::new(ErrorKind::InvalidData, "Message is not valid UTF-8"))
+}
+
+/// Сколько еще байт хедеров можно прочитать, ошибка если уже нисколько
+fn header_limit(read: usize) -> io::Result<u64> {
+  match MAX_HEADER_LENGTH.saturating_sub(read) {
+    0 => Err(Error::new(ErrorKind::InvalidData, "Headers are too long")),
+    v => Ok(v as u64)
+  }
+}
+
+fn connection_closed() -> Error {
+  Error::new(ErrorKind::BrokenPipe, "Connection closed")
+}
+
+impl<R: BufRead> SignalReader for R {
+  fn read_signal(&mut self) -> io::Result<String> {
+    let mut res_line = String::new();
+    let mut content_length = 0;
+    loop {
+      let mut buf_line = String::new();
+      let limit = header_limit(res_line.len())?;
+      if self.by_ref().take(limit).read_line(&mut buf_line)? == 0 {
+        return Err(connection_closed());
+      }
+      res_line.push_str(&buf_line);
+
+      if read_header_line(&buf_line, &mut content_length)? {
+        break;
+      }
+    }
+    check_content_length(content_length)?;
+
+    let mut body = vec![0; content_length];
+    self.read_exact(&mut body)?;
+    res_line.push_str(&decode_body(body)?);
+
+    Ok(res_line)
+  }
+}
+
+#[cfg(feature = "tokio")]
+impl<R: AsyncBufRead + Unpin + Send> AsyncSignalReader for R {
+  async fn read_signal(&mut self) -> io::Result<String> {
+    let mut res_line = String::new();
+    let mut content_length = 0;
+    loop {
+      let mut buf_line = String::new();
+      let limit = header_limit(res_line.len())?;
+      if (&mut *self).take(limit).read_line(&mut buf_line).await? == 0 {
+        return Err(connection_closed());
+      }
+      res_line.push_str(&buf_line);
+
+      if read_header_line(&buf_line, &mut content_length)? {
+        break;
+      }
+    }
+    check_content_length(content_length)?;
+
+    let mut body = vec![0; content_length];
+    self.read_exact(&mut body).await?;
+    res_line.push_str(&decode_body(body)?);
+
+    Ok(res_line)
+  }
+}
@@ -0,0 +1,607 @@
+use std::{
+  str::FromStr,
+  fmt,
+  error::Error
+};
+
+/*
+  Сигнал может содержать следующие хедеры
+  USER:         USERNAME
+  USER:         PASSWORD
+  USER:         KEY
+  USER:         PROTOCOL_VERSION
+  SERVER:       AUTH_STATUS
+  SERVER:       DENIAL_REASON
+  USER+SERVER:  WITH_MESSAGE
+  USER+SERVER:  CONTENT_LENGTH
+  USER+SERVER:  SIGNAL_TYPE
+  USER+SERVER:  ROOM
+  USER+SERVER:  TARGET
+  USER:         LIMIT
+  USER:         SINCE
+  USER:         AFTER
+  SERVER:       SEQ
+  SERVER:       SKIPPED
+  SERVER:       MESSAGE_ID
+  SERVER:       TIMESTAMP
+  SERVER:       EVENT
+  USER+SERVER:  NEW_USERNAME
+  SERVER:       REASON
+  SERVER:       SERVER_MESSAGE
+
+  Хедеры заканчиваются пустой строкой. Если есть WITH_MESSAGE, за ней идет
+  ровно CONTENT_LENGTH байт текста сообщения в UTF-8, поэтому сообщение
+  может содержать любые символы, в том числе пустые строки.
+*/
+
+/// Версия протокола, которую клиент отправляет в CONNECTION сигнале
+pub const PROTOCOL_VERSION: u16 = 1;
+pub const MAX_USERNAME_LENGTH: usize = 32;
+pub const MAX_ROOM_NAME_LENGTH: usize = 32;
+/// Комната, в которую сервер сажает всех при подключении
+pub const DEFAULT_ROOM: &str = "#lobby";
+/// Сколько последних сообщений комнаты получает клиент, если не указал LIMIT
+pub const DEFAULT_HISTORY_LIMIT: usize = 20;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseSignalDataError;
+impl Error for ParseSignalDataError {}
+impl fmt::Display for ParseSignalDataError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "invalid signal data")
+  }
+}
+
+/// Отказ в подключении: код причины и текст, который можно показать пользователю
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuthConnectionError {
+  pub reason: DenialReason,
+  pub text: String,
+}
+impl AuthConnectionError {
+  pub fn new(reason: DenialReason, text: &str) -> AuthConnectionError {
+    AuthConnectionError { reason, text: text.to_owned() }
+  }
+}
+impl Error for AuthConnectionError {}
+impl fmt::Display for AuthConnectionError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}", self.text)
+  }
+}
+
+/// Имя не может быть пустым, слишком длинным или содержать пробелы и
+/// управляющие символы
+pub fn is_valid_username(username: &str) -> bool {
+  !username.is_empty()
+    && username.chars().count() <= MAX_USERNAME_LENGTH
+    && !username.chars().any(|c| c.is_whitespace() || c.is_control())
+}
+
+/// Названия комнат выглядят как `#rust`: `#` и хотя бы один символ, без
+/// пробелов и управляющих символов
+pub fn is_valid_room_name(room: &str) -> bool {
+  room.len() > 1
+    && room.starts_with('#')
+    && room.chars().count() <= MAX_ROOM_NAME_LENGTH
+    && !room.chars().any(|c| c.is_whitespace() || c.is_control())
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignalType {
+  Connection,
+  NewMessage,
+  Join,
+  Part,
+  List,
+  DirectMessage,
+  History,
+  /// Запрос и ответ со списком пользователей комнаты
+  UserList,
+  /// Кто-то вошел в комнату или вышел из нее
+  Presence,
+  /// Смена имени без переподключения, новое имя в NEW_USERNAME
+  Rename,
+  Error,
+}
+
+impl FromStr for SignalType {
+  type Err = ParseSignalDataError;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "CONNECTION" => Ok(SignalType::Connection),
+      "NEW_MESSAGE" => Ok(SignalType::NewMessage),
+      "JOIN" => Ok(SignalType::Join),
+      "PART" => Ok(SignalType::Part),
+      "LIST" => Ok(SignalType::List),
+      "DIRECT_MESSAGE" => Ok(SignalType::DirectMessage),
+      "HISTORY" => Ok(SignalType::History),
+      "USER_LIST" => Ok(SignalType::UserList),
+      "PRESENCE" => Ok(SignalType::Presence),
+      "RENAME" => Ok(SignalType::Rename),
+      "ERROR" => Ok(SignalType::Error),
+      _ => Err(ParseSignalDataError)
+    }
+  }
+}
+
+impl fmt::Display for SignalType {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      SignalType::Connection => write!(f, "CONNECTION"),
+      SignalType::NewMessage => write!(f, "NEW_MESSAGE"),
+      SignalType::Join => write!(f, "JOIN"),
+      SignalType::Part => write!(f, "PART"),
+      SignalType::List => write!(f, "LIST"),
+      SignalType::DirectMessage => write!(f, "DIRECT_MESSAGE"),
+      SignalType::History => write!(f, "HISTORY"),
+      SignalType::UserList => write!(f, "USER_LIST"),
+      SignalType::Presence => write!(f, "PRESENCE"),
+      SignalType::Rename => write!(f, "RENAME"),
+      SignalType::Error => write!(f, "ERROR"),
+    }
+  }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthStatus {
+  ACCEPTED,
+  DENIED
+}
+
+impl FromStr for AuthStatus {
+  type Err = ParseSignalDataError;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "ACCEPTED" => Ok(AuthStatus::ACCEPTED),
+      "DENIED" => Ok(AuthStatus::DENIED),
+      _ => Err(ParseSignalDataError)
+    }
+  }
+}
+
+impl fmt::Display for AuthStatus {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      AuthStatus::ACCEPTED => write!(f, "ACCEPTED"),
+      AuthStatus::DENIED => write!(f, "DENIED")
+    }
+  }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DenialReason {
+  UsernameTaken,
+  InvalidUsername,
+  BadKey,
+  ServerFull,
+  VersionMismatch,
+  InvalidSignal
+}
+
+impl FromStr for DenialReason {
+  type Err = ParseSignalDataError;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "USERNAME_TAKEN" => Ok(DenialReason::UsernameTaken),
+      "INVALID_USERNAME" => Ok(DenialReason::InvalidUsername),
+      "BAD_KEY" => Ok(DenialReason::BadKey),
+      "SERVER_FULL" => Ok(DenialReason::ServerFull),
+      "VERSION_MISMATCH" => Ok(DenialReason::VersionMismatch),
+      "INVALID_SIGNAL" => Ok(DenialReason::InvalidSignal),
+      _ => Err(ParseSignalDataError)
+    }
+  }
+}
+
+impl fmt::Display for DenialReason {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      DenialReason::UsernameTaken => write!(f, "USERNAME_TAKEN"),
+      DenialReason::InvalidUsername => write!(f, "INVALID_USERNAME"),
+      DenialReason::BadKey => write!(f, "BAD_KEY"),
+      DenialReason::ServerFull => write!(f, "SERVER_FULL"),
+      DenialReason::VersionMismatch => write!(f, "VERSION_MISMATCH"),
+      DenialReason::InvalidSignal => write!(f, "INVALID_SIGNAL")
+    }
+  }
+}
+
+
+/// Что произошло с пользователем из PRESENCE сигнала
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PresenceEvent {
+  Joined,
+  Left,
+  /// USERNAME - старое имя, NEW_USERNAME - новое
+  Renamed,
+  /// Сервер отключил пользователя, причина в REASON
+  Kicked
+}
+
+impl FromStr for PresenceEvent {
+  type Err = ParseSignalDataError;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "JOINED" => Ok(PresenceEvent::Joined),
+      "LEFT" => Ok(PresenceEvent::Left),
+      "RENAMED" => Ok(PresenceEvent::Renamed),
+      "KICKED" => Ok(PresenceEvent::Kicked),
+      _ => Err(ParseSignalDataError)
+    }
+  }
+}
+
+impl fmt::Display for PresenceEvent {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      PresenceEvent::Joined => write!(f, "JOINED"),
+      PresenceEvent::Left => write!(f, "LEFT"),
+      PresenceEvent::Renamed => write!(f, "RENAMED"),
+      PresenceEvent::Kicked => write!(f, "KICKED")
+    }
+  }
+}
+
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SignalHeader {
+  Username(String),
+  Password(String),
+  Key(String),
+  ProtocolVersion(u16),
+  AuthStatus(AuthStatus),
+  DenialReason(DenialReason),
+  SignalType(SignalType),
+  Room(String),
+  /// Получатель личного сообщения
+  Target(String),
+  /// Сколько сообщений истории прислать
+  Limit(usize),
+  /// История начиная с этого времени, в секундах от начала эпохи Unix
+  Since(u64),
+  /// История после сообщения с этим порядковым номером, то есть значением
+  /// SEQ, а не MESSAGE_ID
+  After(u64),
+  /// Порядковый номер сообщения в комнате
+  Seq(u64),
+  /// Сколько сообщений клиент пропустил перед этой историей
+  Skipped(u64),
+  MessageId(String),
+  /// Время отправки по UTC, в секундах от начала эпохи Unix
+  Timestamp(u64),
+  Event(PresenceEvent),
+  NewUsername(String),
+  /// Причина события, текст для пользователя
+  Reason(String),
+  WithMessage,
+  ContentLength(usize),
+  ServerMessage
+}
+
+impl FromStr for SignalHeader {
+  type Err = ParseSignalDataError;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let (header, value) = s.split_once(':').unwrap_or((s, s));
+
+    match header {
+      "USERNAME" => Ok(SignalHeader::Username(value.trim().to_owned())),
+      "PASSWORD" => Ok(SignalHeader::Password(value.trim().to_owned())),
+      "KEY" => Ok(SignalHeader::Key(value.trim().to_owned())),
+      "PROTOCOL_VERSION" => {
+        match value.trim().parse::<u16>() {
+          Ok(v) => Ok(SignalHeader::ProtocolVersion(v)),
+          Err(_) => Err(ParseSignalDataError)
+        }
+      },
+      "AUTH_STATUS" => Ok(SignalHeader::AuthStatus(AuthStatus::from_str(value.trim())?)),
+      "DENIAL_REASON" => Ok(SignalHeader::DenialReason(DenialReason::from_str(value.trim())?)),
+      "SIGNAL_TYPE" => Ok(SignalHeader::SignalType(SignalType::from_str(value.trim())?)),
+      "ROOM" => Ok(SignalHeader::Room(value.trim().to_owned())),
+      "TARGET" => Ok(SignalHeader::Target(value.trim().to_owned())),
+      "LIMIT" => {
+        match value.trim().parse::<usize>() {
+          Ok(v) => Ok(SignalHeader::Limit(v)),
+          Err(_) => Err(ParseSignalDataError)
+        }
+      },
+      "SINCE" => {
+        match value.trim().parse::<u64>() {
+          Ok(v) => Ok(SignalHeader::Since(v)),
+          Err(_) => Err(ParseSignalDataError)
+        }
+      },
+      "AFTER" => {
+        match value.trim().parse::<u64>() {
+          Ok(v) => Ok(SignalHeader::After(v)),
+          Err(_) => Err(ParseSignalDataError)
+        }
+      },
+      "SEQ" => {
+        match value.trim().parse::<u64>() {
+          Ok(v) => Ok(SignalHeader::Seq(v)),
+          Err(_) => Err(ParseSignalDataError)
+        }
+      },
+      "SKIPPED" => {
+        match value.trim().parse::<u64>() {
+          Ok(v) => Ok(SignalHeader::Skipped(v)),
+          Err(_) => Err(ParseSignalDataError)
+        }
+      },
+      "MESSAGE_ID" => Ok(SignalHeader::MessageId(value.trim().to_owned())),
+      "TIMESTAMP" => {
+        match value.trim().parse::<u64>() {
+          Ok(v) => Ok(SignalHeader::Timestamp(v)),
+          Err(_) => Err(ParseSignalDataError)
+        }
+      },
+      "EVENT" => Ok(SignalHeader::Event(PresenceEvent::from_str(value.trim())?)),
+      "NEW_USERNAME" => Ok(SignalHeader::NewUsername(value.trim().to_owned())),
+      "REASON" => Ok(SignalHeader::Reason(value.trim().to_owned())),
+      "WITH_MESSAGE" => Ok(SignalHeader::WithMessage),
+      "CONTENT_LENGTH" => {
+        match value.trim().parse::<usize>() {
+          Ok(v) => Ok(SignalHeader::ContentLength(v)),
+          Err(_) => Err(ParseSignalDataError)
+        }
+      },
+      "SERVER_MESSAGE" => Ok(SignalHeader::ServerMessage),
+      _ => Err(ParseSignalDataError)
+    }
+  }
+}
+
+impl fmt::Display for SignalHeader {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      SignalHeader::Username(v) => write!(f, "USERNAME: {v}\r\n"),
+      SignalHeader::Password(v) => write!(f, "PASSWORD: {v}\r\n"),
+      SignalHeader::Key(v) => write!(f, "KEY: {v}\r\n"),
+      SignalHeader::ProtocolVersion(v) => write!(f, "PROTOCOL_VERSION: {v}\r\n"),
+      SignalHeader::AuthStatus(v) => write!(f, "AUTH_STATUS: {v}\r\n"),
+      SignalHeader::DenialReason(v) => write!(f, "DENIAL_REASON: {v}\r\n"),
+      SignalHeader::SignalType(v) => write!(f, "SIGNAL_TYPE: {v}\r\n"),
+      SignalHeader::Room(v) => write!(f, "ROOM: {v}\r\n"),
+      SignalHeader::Target(v) => write!(f, "TARGET: {v}\r\n"),
+      SignalHeader::Limit(v) => write!(f, "LIMIT: {v}\r\n"),
+      SignalHeader::Since(v) => write!(f, "SINCE: {v}\r\n"),
+      SignalHeader::After(v) => write!(f, "AFTER: {v}\r\n"),
+      SignalHeader::Seq(v) => write!(f, "SEQ: {v}\r\n"),
+      SignalHeader::Skipped(v) => write!(f, "SKIPPED: {v}\r\n"),
+      SignalHeader::MessageId(v) => write!(f, "MESSAGE_ID: {v}\r\n"),
+      SignalHeader::Timestamp(v) => write!(f, "TIMESTAMP: {v}\r\n"),
+      SignalHeader::Event(v) => write!(f, "EVENT: {v}\r\n"),
+      SignalHeader::NewUsername(v) => write!(f, "NEW_USERNAME: {v}\r\n"),
+      SignalHeader::Reason(v) => write!(f, "REASON: {v}\r\n"),
+      SignalHeader::WithMessage => write!(f, "WITH_MESSAGE\r\n"),
+      SignalHeader::ContentLength(v) => write!(f, "CONTENT_LENGTH: {v}\r\n"),
+      SignalHeader::ServerMessage => write!(f, "SERVER_MESSAGE\r\n")
+    }
+  }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SignalData {
+  pub username: Option<String>,
+  pub password: Option<String>,
+  pub key: Option<String>,
+  pub protocol_version: Option<u16>,
+  pub auth_status: Option<AuthStatus>,
+  pub denial_reason: Option<DenialReason>,
+  pub signal_type: Option<SignalType>,
+  pub room: Option<String>,
+  pub target: Option<String>,
+  pub limit: Option<usize>,
+  pub since: Option<u64>,
+  pub after: Option<u64>,
+  pub seq: Option<u64>,
+  pub skipped: Option<u64>,
+  pub message_id: Option<String>,
+  pub timestamp: Option<u64>,
+  pub event: Option<PresenceEvent>,
+  pub new_username: Option<String>,
+  pub reason: Option<String>,
+  pub with_message: bool,
+  pub message: Option<String>,
+  pub server_message: bool
+}
+
+impl SignalData {
+  pub fn new(headers: Vec<SignalHeader>, message: Option<&str>) -> SignalData {
+    let mut data = SignalData::default();
+
+    for header in headers {
+      data.apply_header(header);
+    }
+    if data.with_message {
+      data.message = Some(message.unwrap_or("").to_owned());
+    }
+
+    data
+  }
+
+  fn apply_header(&mut self, header: SignalHeader) {
+    match header {
+      SignalHeader::Username(v) => {
+        self.username = Some(v);
+      },
+      SignalHeader::Password(v) => {
+        self.password = Some(v);
+      },
+      SignalHeader::Key(v) => {
+        self.key = Some(v);
+      },
+      SignalHeader::ProtocolVersion(v) => {
+        self.protocol_version = Some(v);
+      },
+      SignalHeader::AuthStatus(v) => {
+        self.auth_status = Some(v);
+      },
+      SignalHeader::DenialReason(v) => {
+        self.denial_reason = Some(v);
+      },
+      SignalHeader::SignalType(v) => {
+        self.signal_type = Some(v);
+      },
+      SignalHeader::Room(v) => {
+        self.room = Some(v);
+      },
+      SignalHeader::Target(v) => {
+        self.target = Some(v);
+      },
+      SignalHeader::Limit(v) => {
+        self.limit = Some(v);
+      },
+      SignalHeader::Since(v) => {
+        self.since = Some(v);
+      },
+      SignalHeader::After(v) => {
+        self.after = Some(v);
+      },
+      SignalHeader::Seq(v) => {
+        self.seq = Some(v);
+      },
+      SignalHeader::Skipped(v) => {
+        self.skipped = Some(v);
+      },
+      SignalHeader::MessageId(v) => {
+        self.message_id = Some(v);
+      },
+      SignalHeader::Timestamp(v) => {
+        self.timestamp = Some(v);
+      },
+      SignalHeader::Event(v) => {
+        self.event = Some(v);
+      },
+      SignalHeader::NewUsername(v) => {
+        self.new_username = Some(v);
+      },
+      SignalHeader::Reason(v) => {
+        self.reason = Some(v);
+      },
+      SignalHeader::WithMessage => {
+        self.with_message = true;
+      },
+      // Длина считается при кодировании, хранить ее незачем
+      SignalHeader::ContentLength(_) => {},
+      SignalHeader::ServerMessage => {
+        self.server_message = true;
+      }
+    }
+  }
+}
+
+impl FromStr for SignalData {
+  type Err = ParseSignalDataError;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let mut data = SignalData::default();
+    let mut content_length = None;
+
+    // Хедеры читаем только до первой пустой строки, чтобы текст сообщения
+    // не мог их подменить
+    let (headers, body) = s.split_once("\r\n\r\n").ok_or(ParseSignalDataError)?;
+    for string in headers.split("\r\n") {
+      match SignalHeader::from_str(string) {
+        Ok(SignalHeader::ContentLength(v)) => content_length = Some(v),
+        Ok(header) => data.apply_header(header),
+        Err(_) => continue
+      }
+    }
+
+    if data.with_message {
+      match content_length {
+        Some(v) if v == body.len() => data.message = Some(body.to_owned()),
+        _ => return Err(ParseSignalDataError)
+      }
+    }
+
+    if data.signal_type.is_none() {
+      return Err(ParseSignalDataError)
+    }
+
+    Ok(data)
+  }
+}
+
+impl fmt::Display for SignalData {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    if let Some(v) = &self.username {
+      write!(f, "{}", SignalHeader::Username(v.to_owned()))?;
+    }
+    if let Some(v) = &self.password {
+      write!(f, "{}", SignalHeader::Password(v.to_owned()))?;
+    }
+    if let Some(v) = &self.key {
+      write!(f, "{}", SignalHeader::Key(v.to_owned()))?;
+    }
+    if let Some(v) = self.protocol_version {
+      write!(f, "{}", SignalHeader::ProtocolVersion(v))?;
+    }
+    if let Some(v) = self.auth_status {
+      write!(f, "{}", SignalHeader::AuthStatus(v))?;
+    }
+    if let Some(v) = self.denial_reason {
+      write!(f, "{}", SignalHeader::DenialReason(v))?;
+    }
+    if let Some(v) = self.signal_type {
+      write!(f, "{}", SignalHeader::SignalType(v))?;
+    }
+    if let Some(v) = &self.room {
+      write!(f, "{}", SignalHeader::Room(v.to_owned()))?;
+    }
+    if let Some(v) = &self.target {
+      write!(f, "{}", SignalHeader::Target(v.to_owned()))?;
+    }
+    if let Some(v) = self.limit {
+      write!(f, "{}", SignalHeader::Limit(v))?;
+    }
+    if let Some(v) = self.since {
+      write!(f, "{}", SignalHeader::Since(v))?;
+    }
+    if let Some(v) = self.after {
+      write!(f, "{}", SignalHeader::After(v))?;
+    }
+    if let Some(v) = self.seq {
+      write!(f, "{}", SignalHeader::Seq(v))?;
+    }
+    if let Some(v) = self.skipped {
+      write!(f, "{}", SignalHeader::Skipped(v))?;
+    }
+    if let Some(v) = &self.message_id {
+      write!(f, "{}", SignalHeader::MessageId(v.to_owned()))?;
+    }
+    if let Some(v) = self.timestamp {
+      write!(f, "{}", SignalHeader::Timestamp(v))?;
+    }
+    if let Some(v) = self.event {
+      write!(f, "{}", SignalHeader::Event(v))?;
+    }
+    if let Some(v) = &self.new_username {
+      write!(f, "{}", SignalHeader::NewUsername(v.to_owned()))?;
+    }
+    if let Some(v) = &self.reason {
+      write!(f, "{}", SignalHeader::Reason(v.to_owned()))?;
+    }
+    if self.server_message {
+      write!(f, "{}", SignalHeader::ServerMessage)?;
+    }
+    match (self.with_message, &self.message) {
+      (true, Some(v)) => {
+        write!(f, "{}", SignalHeader::WithMessage)?;
+        write!(f, "{}", SignalHeader::ContentLength(v.len()))?;
+        write!(f, "\r\n{v}")
+      },
+      _ => write!(f, "\r\n")
+    }
+  }
+}
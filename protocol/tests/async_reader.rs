@@ -0,0 +1,43 @@
+#![cfg(feature = "tokio")]
+
+use std::{io::ErrorKind, str::FromStr};
+
+use tokio::io::BufReader;
+
+use tchat_protocol::{
+  AsyncSignalReader,
+  SignalData,
+  SignalHeader,
+  SignalType,
+  MAX_HEADER_LENGTH
+};
+
+#[tokio::test]
+async fn reads_consecutive_signals() {
+  let first = SignalData::new(
+    vec![
+      SignalHeader::SignalType(SignalType::NewMessage),
+      SignalHeader::WithMessage
+    ],
+    Some("first\r\n\r\nstill first")
+  );
+  let second = SignalData::new(
+    vec![SignalHeader::SignalType(SignalType::Connection)],
+    None
+  );
+  let bytes = format!("{first}{second}").into_bytes();
+  let mut reader = BufReader::new(bytes.as_slice());
+
+  for expected in [first, second] {
+    let raw = reader.read_signal().await.unwrap();
+    assert_eq!(SignalData::from_str(&raw).unwrap(), expected);
+  }
+  assert_eq!(reader.read_signal().await.unwrap_err().kind(), ErrorKind::BrokenPipe);
+}
+
+#[tokio::test]
+async fn endless_header_line_is_rejected() {
+  let bytes = format!("USERNAME: {}", "a".repeat(MAX_HEADER_LENGTH * 4)).into_bytes();
+  let mut reader = BufReader::new(bytes.as_slice());
+  assert_eq!(reader.read_signal().await.unwrap_err().kind(), ErrorKind::InvalidData);
+}
@@ -0,0 +1,83 @@
+use std::{
+  io::{Cursor, ErrorKind},
+  str::FromStr
+};
+
+use tchat_protocol::{
+  SignalData,
+  SignalHeader,
+  SignalReader,
+  SignalType,
+  MAX_CONTENT_LENGTH,
+  MAX_HEADER_LENGTH
+};
+
+fn message_signal(text: &str) -> SignalData {
+  SignalData::new(
+    vec![
+      SignalHeader::SignalType(SignalType::NewMessage),
+      SignalHeader::Username("ivan".to_owned()),
+      SignalHeader::WithMessage
+    ],
+    Some(text)
+  )
+}
+
+#[test]
+fn reads_consecutive_signals() {
+  let first = message_signal("first\r\n\r\nstill first");
+  let second = SignalData::new(
+    vec![
+      SignalHeader::SignalType(SignalType::Connection),
+      SignalHeader::Username("ivan".to_owned())
+    ],
+    None
+  );
+  let third = message_signal("third");
+  let mut reader = Cursor::new(format!("{first}{second}{third}"));
+
+  for expected in [first, second, third] {
+    let raw = reader.read_signal().unwrap();
+    assert_eq!(SignalData::from_str(&raw).unwrap(), expected);
+  }
+}
+
+#[test]
+fn closed_stream_is_reported() {
+  let mut reader = Cursor::new("");
+  assert_eq!(reader.read_signal().unwrap_err().kind(), ErrorKind::BrokenPipe);
+}
+
+#[test]
+fn truncated_message_is_reported() {
+  let mut reader = Cursor::new("SIGNAL_TYPE: NEW_MESSAGE\r\nWITH_MESSAGE\r\nCONTENT_LENGTH: 10\r\n\r\nhello");
+  assert_eq!(reader.read_signal().unwrap_err().kind(), ErrorKind::UnexpectedEof);
+}
+
+#[test]
+fn too_long_message_is_rejected() {
+  let mut reader = Cursor::new(format!(
+    "SIGNAL_TYPE: NEW_MESSAGE\r\nWITH_MESSAGE\r\nCONTENT_LENGTH: {}\r\n\r\n",
+    MAX_CONTENT_LENGTH + 1
+  ));
+  assert_eq!(reader.read_signal().unwrap_err().kind(), ErrorKind::InvalidData);
+}
+
+#[test]
+fn endless_header_line_is_rejected() {
+  let mut reader = Cursor::new(format!("USERNAME: {}", "a".repeat(MAX_HEADER_LENGTH * 4)));
+  assert_eq!(reader.read_signal().unwrap_err().kind(), ErrorKind::InvalidData);
+}
+
+#[test]
+fn many_header_lines_are_rejected() {
+  let mut reader = Cursor::new("ROOM: #rust\r\n".repeat(MAX_HEADER_LENGTH));
+  assert_eq!(reader.read_signal().unwrap_err().kind(), ErrorKind::InvalidData);
+}
+
+#[test]
+fn header_line_without_carriage_return_is_rejected() {
+  let mut reader = Cursor::new("SIGNAL_TYPE: LIST\n\r\n");
+  assert_eq!(reader.read_signal().unwrap_err().kind(), ErrorKind::InvalidData);
+  assert!(SignalData::from_str("SIGNAL_TYPE: LIST\n\r\n").is_err());
+}
@@ -0,0 +1,389 @@
+use std::str::FromStr;
+
+use tchat_protocol::{
+  is_valid_room_name,
+  is_valid_username,
+  AuthStatus,
+  DenialReason,
+  PresenceEvent,
+  SignalData,
+  SignalHeader,
+  SignalType
+};
+
+fn round_trip(data: &SignalData) -> SignalData {
+  SignalData::from_str(&data.to_string()).unwrap()
+}
+
+#[test]
+fn connection_signal_round_trip() {
+  let data = SignalData::new(
+    vec![
+      SignalHeader::SignalType(SignalType::Connection),
+      SignalHeader::Username("ivan".to_owned()),
+      SignalHeader::Password("secret".to_owned()),
+      SignalHeader::Key("server-key".to_owned()),
+      SignalHeader::ProtocolVersion(1)
+    ],
+    None
+  );
+
+  assert_eq!(round_trip(&data), data);
+}
+
+#[test]
+fn auth_status_round_trip() {
+  for status in [AuthStatus::ACCEPTED, AuthStatus::DENIED] {
+    let data = SignalData::new(
+      vec![
+        SignalHeader::SignalType(SignalType::Connection),
+        SignalHeader::AuthStatus(status)
+      ],
+      None
+    );
+
+    assert_eq!(round_trip(&data).auth_status, Some(status));
+  }
+}
+
+#[test]
+fn new_message_round_trip() {
+  let data = SignalData::new(
+    vec![
+      SignalHeader::SignalType(SignalType::NewMessage),
+      SignalHeader::Username("ivan".to_owned()),
+      SignalHeader::WithMessage
+    ],
+    Some("hello there")
+  );
+
+  let decoded = round_trip(&data);
+  assert_eq!(decoded, data);
+  assert_eq!(decoded.message.as_deref(), Some("hello there"));
+}
+
+#[test]
+fn server_message_round_trip() {
+  let data = SignalData::new(
+    vec![
+      SignalHeader::SignalType(SignalType::NewMessage),
+      SignalHeader::Username(String::new()),
+      SignalHeader::WithMessage,
+      SignalHeader::ServerMessage
+    ],
+    Some("ivan joined the chat!")
+  );
+
+  let decoded = round_trip(&data);
+  assert!(decoded.server_message);
+  assert_eq!(decoded, data);
+}
+
+#[test]
+fn empty_message_round_trip() {
+  let data = SignalData::new(
+    vec![
+      SignalHeader::SignalType(SignalType::NewMessage),
+      SignalHeader::WithMessage
+    ],
+    None
+  );
+
+  assert_eq!(round_trip(&data).message.as_deref(), Some(""));
+}
+
+#[test]
+fn message_body_does_not_override_headers() {
+  let data = SignalData::new(
+    vec![
+      SignalHeader::SignalType(SignalType::NewMessage),
+      SignalHeader::Username("ivan".to_owned()),
+      SignalHeader::WithMessage
+    ],
+    Some("USERNAME: admin")
+  );
+
+  let decoded = round_trip(&data);
+  assert_eq!(decoded.username.as_deref(), Some("ivan"));
+  assert_eq!(decoded.message.as_deref(), Some("USERNAME: admin"));
+}
+
+#[test]
+fn signal_without_type_is_rejected() {
+  assert!(SignalData::from_str("USERNAME: ivan\r\n\r\n").is_err());
+}
+
+#[test]
+fn unknown_header_values_are_rejected() {
+  assert!(SignalHeader::from_str("SIGNAL_TYPE: PING").is_err());
+  assert!(SignalHeader::from_str("AUTH_STATUS: MAYBE").is_err());
+  assert!(SignalHeader::from_str("NOT_A_HEADER").is_err());
+}
+
+#[test]
+fn header_encoding() {
+  assert_eq!(SignalHeader::Username("ivan".to_owned()).to_string(), "USERNAME: ivan\r\n");
+  assert_eq!(SignalHeader::Key("k".to_owned()).to_string(), "KEY: k\r\n");
+  assert_eq!(SignalHeader::AuthStatus(AuthStatus::DENIED).to_string(), "AUTH_STATUS: DENIED\r\n");
+  assert_eq!(SignalHeader::SignalType(SignalType::NewMessage).to_string(), "SIGNAL_TYPE: NEW_MESSAGE\r\n");
+  assert_eq!(SignalHeader::WithMessage.to_string(), "WITH_MESSAGE\r\n");
+}
+
+#[test]
+fn message_with_blank_lines_round_trip() {
+  let text = "fn main() {\r\n\r\n  println!(\"hi\");\r\n}\r\n\r\n";
+  let data = SignalData::new(
+    vec![
+      SignalHeader::SignalType(SignalType::NewMessage),
+      SignalHeader::WithMessage
+    ],
+    Some(text)
+  );
+
+  assert_eq!(round_trip(&data).message.as_deref(), Some(text));
+}
+
+#[test]
+fn multibyte_message_round_trip() {
+  let text = "привет 👋";
+  let data = SignalData::new(
+    vec![
+      SignalHeader::SignalType(SignalType::NewMessage),
+      SignalHeader::WithMessage
+    ],
+    Some(text)
+  );
+
+  assert!(data.to_string().contains(&format!("CONTENT_LENGTH: {}\r\n", text.len())));
+  assert_eq!(round_trip(&data).message.as_deref(), Some(text));
+}
+
+#[test]
+fn wrong_content_length_is_rejected() {
+  let signal = "SIGNAL_TYPE: NEW_MESSAGE\r\nWITH_MESSAGE\r\nCONTENT_LENGTH: 10\r\n\r\nhello";
+  assert!(SignalData::from_str(signal).is_err());
+
+  let signal = "SIGNAL_TYPE: NEW_MESSAGE\r\nWITH_MESSAGE\r\n\r\nhello";
+  assert!(SignalData::from_str(signal).is_err());
+}
+
+#[test]
+fn denial_reason_round_trip() {
+  let reasons = [
+    DenialReason::UsernameTaken,
+    DenialReason::InvalidUsername,
+    DenialReason::BadKey,
+    DenialReason::ServerFull,
+    DenialReason::VersionMismatch,
+    DenialReason::InvalidSignal
+  ];
+  for reason in reasons {
+    let data = SignalData::new(
+      vec![
+        SignalHeader::SignalType(SignalType::Connection),
+        SignalHeader::AuthStatus(AuthStatus::DENIED),
+        SignalHeader::DenialReason(reason),
+        SignalHeader::WithMessage
+      ],
+      Some("Server is full (10/10)")
+    );
+
+    let decoded = round_trip(&data);
+    assert_eq!(decoded.denial_reason, Some(reason));
+    assert_eq!(decoded.message.as_deref(), Some("Server is full (10/10)"));
+  }
+}
+
+#[test]
+fn username_validation() {
+  assert!(is_valid_username("ivan"));
+  assert!(is_valid_username("иван_33"));
+  assert!(!is_valid_username(""));
+  assert!(!is_valid_username("two words"));
+  assert!(!is_valid_username("tab\there"));
+  assert!(!is_valid_username(&"a".repeat(33)));
+}
+
+#[test]
+fn room_signal_round_trip() {
+  for signal_type in [SignalType::Join, SignalType::Part] {
+    let data = SignalData::new(
+      vec![
+        SignalHeader::SignalType(signal_type),
+        SignalHeader::Room("#rust".to_owned())
+      ],
+      None
+    );
+
+    let decoded = round_trip(&data);
+    assert_eq!(decoded, data);
+    assert_eq!(decoded.room.as_deref(), Some("#rust"));
+  }
+}
+
+#[test]
+fn direct_message_round_trip() {
+  let data = SignalData::new(
+    vec![
+      SignalHeader::SignalType(SignalType::DirectMessage),
+      SignalHeader::Username("ivan".to_owned()),
+      SignalHeader::Target("maria".to_owned()),
+      SignalHeader::WithMessage
+    ],
+    Some("hi, just between us")
+  );
+
+  let decoded = round_trip(&data);
+  assert_eq!(decoded, data);
+  assert_eq!(decoded.target.as_deref(), Some("maria"));
+}
+
+#[test]
+fn history_request_round_trip() {
+  let data = SignalData::new(
+    vec![
+      SignalHeader::SignalType(SignalType::History),
+      SignalHeader::Room("#rust".to_owned()),
+      SignalHeader::Limit(50),
+      SignalHeader::Since(1700000000),
+      SignalHeader::After(120)
+    ],
+    None
+  );
+
+  let decoded = round_trip(&data);
+  assert_eq!(decoded, data);
+  assert_eq!(decoded.limit, Some(50));
+  assert_eq!(decoded.since, Some(1700000000));
+  assert!(SignalHeader::from_str("LIMIT: many").is_err());
+}
+
+#[test]
+fn history_gap_round_trip() {
+  let data = SignalData::new(
+    vec![
+      SignalHeader::SignalType(SignalType::History),
+      SignalHeader::Room("#rust".to_owned()),
+      SignalHeader::Skipped(40),
+      SignalHeader::ServerMessage,
+      SignalHeader::WithMessage
+    ],
+    Some("40 messages skipped")
+  );
+
+  let decoded = round_trip(&data);
+  assert_eq!(decoded, data);
+  assert_eq!(decoded.skipped, Some(40));
+}
+
+#[test]
+fn message_id_and_timestamp_round_trip() {
+  let data = SignalData::new(
+    vec![
+      SignalHeader::SignalType(SignalType::NewMessage),
+      SignalHeader::Room("#rust".to_owned()),
+      SignalHeader::Seq(7),
+      SignalHeader::MessageId("0b7f0d3e-3c2a-4d8e-9a59-2f4f8f1f2c11".to_owned()),
+      SignalHeader::Timestamp(1700000000),
+      SignalHeader::Username("ivan".to_owned()),
+      SignalHeader::WithMessage
+    ],
+    Some("hello")
+  );
+
+  let decoded = round_trip(&data);
+  assert_eq!(decoded, data);
+  assert_eq!(decoded.message_id.as_deref(), Some("0b7f0d3e-3c2a-4d8e-9a59-2f4f8f1f2c11"));
+  assert_eq!(decoded.timestamp, Some(1700000000));
+  assert!(SignalHeader::from_str("TIMESTAMP: yesterday").is_err());
+}
+
+#[test]
+fn room_name_validation() {
+  assert!(is_valid_room_name("#lobby"));
+  assert!(is_valid_room_name("#комната"));
+  assert!(!is_valid_room_name("#"));
+  assert!(!is_valid_room_name("lobby"));
+  assert!(!is_valid_room_name("#two words"));
+  assert!(!is_valid_room_name(&format!("#{}", "a".repeat(32))));
+}
+
+#[test]
+fn presence_round_trip() {
+  let data = SignalData::new(
+    vec![
+      SignalHeader::SignalType(SignalType::Presence),
+      SignalHeader::Room("#rust".to_owned()),
+      SignalHeader::Username("ivan".to_owned()),
+      SignalHeader::Timestamp(1700000000),
+      SignalHeader::Event(PresenceEvent::Left)
+    ],
+    None
+  );
+
+  let decoded = round_trip(&data);
+  assert_eq!(decoded, data);
+  assert_eq!(decoded.event, Some(PresenceEvent::Left));
+  assert!(SignalHeader::from_str("EVENT: WAVED").is_err());
+}
+
+#[test]
+fn kick_and_rename_round_trip() {
+  let kicked = SignalData::new(
+    vec![
+      SignalHeader::SignalType(SignalType::Presence),
+      SignalHeader::Room("#rust".to_owned()),
+      SignalHeader::Username("ivan".to_owned()),
+      SignalHeader::Event(PresenceEvent::Kicked),
+      SignalHeader::Reason("Too many messages behind".to_owned())
+    ],
+    None
+  );
+  let renamed = SignalData::new(
+    vec![
+      SignalHeader::SignalType(SignalType::Presence),
+      SignalHeader::Room("#rust".to_owned()),
+      SignalHeader::Username("ivan".to_owned()),
+      SignalHeader::Event(PresenceEvent::Renamed),
+      SignalHeader::NewUsername("vanya".to_owned())
+    ],
+    None
+  );
+
+  assert_eq!(round_trip(&kicked), kicked);
+  assert_eq!(round_trip(&kicked).reason.as_deref(), Some("Too many messages behind"));
+  assert_eq!(round_trip(&renamed), renamed);
+  assert_eq!(round_trip(&renamed).new_username.as_deref(), Some("vanya"));
+}
+
+#[test]
+fn user_list_round_trip() {
+  let data = SignalData::new(
+    vec![
+      SignalHeader::SignalType(SignalType::UserList),
+      SignalHeader::Room("#rust".to_owned()),
+      SignalHeader::WithMessage
+    ],
+    Some("anna\nivan")
+  );
+
+  let decoded = round_trip(&data);
+  assert_eq!(decoded, data);
+  assert_eq!(decoded.message.as_deref(), Some("anna\nivan"));
+}
+
+#[test]
+fn rename_round_trip() {
+  let data = SignalData::new(
+    vec![
+      SignalHeader::SignalType(SignalType::Rename),
+      SignalHeader::NewUsername("ivan".to_owned())
+    ],
+    None
+  );
+
+  let decoded = round_trip(&data);
+  assert_eq!(decoded, data);
+  assert_eq!(decoded.signal_type, Some(SignalType::Rename));
+  assert_eq!(decoded.new_username.as_deref(), Some("ivan"));
+}
@@ -0,0 +1,183 @@
+//! Сравнивает `MessagesPool` на подписках с прежней схемой, где каждое
+//! соединение опрашивало пул раз в 10 мс
+//!
+//! Запуск: `cargo bench -p tchat-server --bench broadcast`
+
+use std::{
+  collections::VecDeque,
+  sync::{
+    atomic::{AtomicBool, AtomicU64, Ordering},
+    Arc
+  },
+  thread,
+  time::{Duration, Instant}
+};
+use parking_lot::Mutex;
+
+use tchat_protocol::DEFAULT_ROOM;
+use tchat_server::messages_pool::{MessagesPool, PoolMessage, POOL_SIZE};
+
+const MESSAGES: usize = 200;
+const LATENCY_SAMPLES: usize = 20;
+const IDLE_PERIOD: Duration = Duration::from_secs(1);
+
+fn message(id: usize) -> PoolMessage {
+  PoolMessage {
+    id: id.to_string(),
+    seq: 0,
+    room: DEFAULT_ROOM.to_owned(),
+    username: "bench".to_owned(),
+    message: "hello".to_owned(),
+    from_server: false,
+    timestamp: 0
+  }
+}
+
+/// Прежняя схема: кольцо, из которого каждое соединение читает по id
+/// последнего увиденного сообщения
+struct PollingPool {
+  pool: VecDeque<PoolMessage>,
+}
+
+impl PollingPool {
+  fn push(&mut self, v: PoolMessage) {
+    if self.pool.len() == POOL_SIZE {
+      self.pool.pop_front();
+    }
+    self.pool.push_back(v);
+  }
+
+  fn read_from(&self, id: &str) -> Vec<PoolMessage> {
+    let pool = Vec::from(self.pool.clone());
+    match pool.iter().position(|v| v.id == id) {
+      Some(index) => pool[index + 1..].to_vec(),
+      None => pool
+    }
+  }
+}
+
+struct Report {
+  idle_wakeups: u64,
+  latency: Duration,
+  burst: Duration,
+}
+
+/// Меряет пул, когда все соединения уже запущены: пробуждения, пока никто не
+/// пишет, среднее время, за которое одно сообщение доходит до всех
+/// соединений, и время доставки пачки сообщений
+fn measure(connections: usize, wakeups: &AtomicU64, received: &AtomicU64, push: impl Fn(usize)) -> Report {
+  thread::sleep(IDLE_PERIOD);
+  let idle_wakeups = wakeups.load(Ordering::Relaxed);
+
+  let wait_for = |expected: u64| {
+    while received.load(Ordering::Relaxed) < expected {
+      thread::yield_now();
+    }
+  };
+
+  let mut latency = Duration::ZERO;
+  for id in 0..LATENCY_SAMPLES {
+    let started = Instant::now();
+    push(id);
+    wait_for((connections * (id + 1)) as u64);
+    latency += started.elapsed();
+    thread::sleep(Duration::from_millis(15));
+  }
+
+  let started = Instant::now();
+  for id in LATENCY_SAMPLES..LATENCY_SAMPLES + MESSAGES {
+    push(id);
+  }
+  wait_for((connections * (LATENCY_SAMPLES + MESSAGES)) as u64);
+  let burst = started.elapsed();
+
+  Report {
+    idle_wakeups,
+    latency: latency / LATENCY_SAMPLES as u32,
+    burst
+  }
+}
+
+fn bench_polling(connections: usize) -> Report {
+  let pool = Arc::new(Mutex::new(PollingPool { pool: VecDeque::new() }));
+  let stop = Arc::new(AtomicBool::new(false));
+  let wakeups = Arc::new(AtomicU64::new(0));
+  let received = Arc::new(AtomicU64::new(0));
+
+  let handles: Vec<_> = (0..connections).map(|_| {
+    let pool = pool.clone();
+    let stop = stop.clone();
+    let wakeups = wakeups.clone();
+    let received = received.clone();
+    thread::spawn(move || {
+      let mut last_read = String::new();
+      while !stop.load(Ordering::Relaxed) {
+        wakeups.fetch_add(1, Ordering::Relaxed);
+        let messages = pool.lock().read_from(&last_read);
+        if let Some(v) = messages.last() {
+          last_read = v.id.clone();
+        }
+        received.fetch_add(messages.len() as u64, Ordering::Relaxed);
+        thread::sleep(Duration::from_millis(10));
+      }
+    })
+  }).collect();
+
+  let report = measure(connections, &wakeups, &received, |id| pool.lock().push(message(id)));
+
+  stop.store(true, Ordering::Relaxed);
+  handles.into_iter().for_each(|v| v.join().unwrap());
+
+  report
+}
+
+fn bench_subscriptions(connections: usize) -> Report {
+  // Очередь вмещает все сообщения, чтобы никого не отключило как медленного
+  let pool = Arc::new(Mutex::new(MessagesPool::new(LATENCY_SAMPLES + MESSAGES)));
+  let wakeups = Arc::new(AtomicU64::new(0));
+  let received = Arc::new(AtomicU64::new(0));
+
+  let handles: Vec<_> = (0..connections).map(|index| {
+    let mut subscription = {
+      let username = format!("bench{index}");
+      let mut pool = pool.lock();
+      let subscription = pool.subscribe(&username);
+      pool.join(DEFAULT_ROOM, subscription.id, &username);
+      subscription
+    };
+    let wakeups = wakeups.clone();
+    let received = received.clone();
+    thread::spawn(move || {
+      while subscription.receiver.blocking_recv().is_some() {
+        wakeups.fetch_add(1, Ordering::Relaxed);
+        received.fetch_add(1, Ordering::Relaxed);
+      }
+    })
+  }).collect();
+
+  let report = measure(connections, &wakeups, &received, |id| pool.lock().push(message(id)));
+
+  // Без подписчиков у пула каналы закрываются и потоки завершаются
+  drop(pool);
+  handles.into_iter().for_each(|v| v.join().unwrap());
+
+  report
+}
+
+fn main() {
+  println!(
+    "{:>11} | {:>12} | {:>14} | {:>13} | {:>15}",
+    "connections", "design", "idle wakeups/s", "avg latency", "burst of 200"
+  );
+  for connections in [10, 100, 500] {
+    for (design, report) in [
+      ("polling", bench_polling(connections)),
+      ("subscription", bench_subscriptions(connections))
+    ] {
+      println!(
+        "{:>11} | {:>12} | {:>14} | {:>13.2?} | {:>15.2?}",
+        connections, design, report.idle_wakeups, report.latency, report.burst
+      );
+    }
+  }
+}
@@ -0,0 +1,14 @@
+use std::{
+  fmt,
+  error::Error
+};
+
+/// Ошибка в запросе клиента, текст отправляется ему в ERROR сигнале
+#[derive(Debug)]
+pub struct IncomingMessageError(pub String);
+impl Error for IncomingMessageError {}
+impl fmt::Display for IncomingMessageError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "incoming message error: {}", self.0)
+  }
+}
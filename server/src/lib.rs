@@ -0,0 +1,7 @@
+pub mod settings;
+pub mod state;
+pub mod service;
+pub mod managers;
+pub mod messages_pool;
+pub mod storage;
+pub mod errors;
@@ -1,22 +1,17 @@
 use anyhow::Result;
 
-use service::Service;
-use settings::Settings;
-use state::State;
+use tchat_server::{
+  service::Service,
+  settings::Settings,
+  state::State
+};
 
-mod settings;
-mod state;
-mod service;
-mod managers;
-mod messages_pool;
-mod reader;
-mod types;
-
-fn main() -> Result<()> {
+#[tokio::main]
+async fn main() -> Result<()> {
   let settings = Settings::new();
   let state = State::new(settings);
 
-  Service::run(state)?;
+  Service::run(state).await?;
   
   Ok(())
-}
\ No newline at end of file
+}
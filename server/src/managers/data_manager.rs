@@ -1,143 +1,373 @@
 use std::sync::Arc;
-use std::sync::mpsc::Receiver;
-use std::thread;
-use std::time::Duration;
 use std::str::FromStr;
 use anyhow::Result;
 use parking_lot::Mutex;
 use uuid::Uuid;
 
-use crate::messages_pool::{PoolMessage, MessagesPool};
-use crate::state::UserData;
-use crate::types::{
+use crate::messages_pool::{unix_timestamp, History, HistoryQuery, PoolMessage, MessagesPool, Subscription, Outgoing};
+use crate::state::{check_sender, State};
+use crate::errors::IncomingMessageError;
+use tchat_protocol::{
+  DEFAULT_ROOM,
+  is_valid_username,
+  is_valid_room_name,
+  AuthConnectionError,
   AuthStatus, 
+  DenialReason,
+  PresenceEvent,
   SignalData, 
   SignalHeader, 
-  AuthConnectionError,
-  IncomingMessageError,
   SignalType
 };
 
 use super::manager::Manager;
 use super::stream_manager::StreamManager;
 
+/// HISTORY сигналы для клиента. Если он что-то пропустил, первым идет
+/// серверное сообщение с количеством пропущенных, а если история сброшена -
+/// сообщение о том, что часть ее потеряна.
+fn history_signals(room: &str, history: History) -> Vec<Arc<SignalData>> {
+  let mut signals = Vec::with_capacity(history.messages.len() + 1);
+  if history.reset {
+    signals.push(Arc::new(SignalData::new(
+      vec![
+        SignalHeader::SignalType(SignalType::History),
+        SignalHeader::Room(room.to_owned()),
+        SignalHeader::ServerMessage,
+        SignalHeader::WithMessage
+      ],
+      Some(&format!("History of {room} was reset on the server, some messages are lost"))
+    )));
+  }
+  if history.skipped > 0 {
+    signals.push(Arc::new(SignalData::new(
+      vec![
+        SignalHeader::SignalType(SignalType::History),
+        SignalHeader::Room(room.to_owned()),
+        SignalHeader::Skipped(history.skipped),
+        SignalHeader::ServerMessage,
+        SignalHeader::WithMessage
+      ],
+      Some(&format!("{} messages skipped", history.skipped))
+    )));
+  }
+  for v in history.messages {
+    signals.push(Arc::new(v.to_signal(SignalType::History)));
+  }
+  signals
+}
+
+/// PRESENCE сигнал для участников комнаты. В `details` - хедеры события,
+/// например REASON.
+fn presence_signal(room: &str, username: &str, event: PresenceEvent, details: Vec<SignalHeader>) -> Arc<SignalData> {
+  let mut headers = vec![
+    SignalHeader::SignalType(SignalType::Presence),
+    SignalHeader::Room(room.to_owned()),
+    SignalHeader::Username(username.to_owned()),
+    SignalHeader::Timestamp(unix_timestamp()),
+    SignalHeader::Event(event)
+  ];
+  headers.extend(details);
+  Arc::new(SignalData::new(headers, None))
+}
+
 pub trait DataManager {
-  fn deny_auth(&mut self) -> Result<()>;
-  fn auth(&mut self, signal: String) -> Result<()>;
-  fn remove_user(&mut self, username: String) -> Result<()>;
-  fn process_messages_pool(&mut self, receiver: Receiver<()>) -> Result<()>;
-  fn process_incoming_message(messages_pool: Arc<Mutex<MessagesPool>>, signal: String) -> Result<()>;
+  async fn deny_auth(&mut self, error: &AuthConnectionError) -> Result<()>;
+  async fn auth(&mut self, signal: String) -> Result<SignalData>;
+  fn join_room(messages_pool: &Mutex<MessagesPool>, subscription_id: u64, username: &str, room: &str, query: &HistoryQuery, max_rooms: usize) -> Result<()>;
+  fn send_history(messages_pool: &Mutex<MessagesPool>, subscription_id: u64, room: &str, query: &HistoryQuery) -> Result<()>;
+  fn part_room(messages_pool: &Mutex<MessagesPool>, subscription_id: u64, username: &str, room: &str) -> Result<()>;
+  fn list_rooms(messages_pool: &Mutex<MessagesPool>, subscription_id: u64);
+  fn list_users(messages_pool: &Mutex<MessagesPool>, subscription_id: u64, room: &str);
+  fn rename_user(state: &State, messages_pool: &Mutex<MessagesPool>, subscription_id: u64, username: &Mutex<String>, data: SignalData) -> Result<()>;
+  fn remove_user(&mut self) -> Result<()>;
+  async fn process_messages_pool(&mut self, subscription: Subscription) -> Result<()>;
+  fn process_incoming_signal(state: &State, messages_pool: Arc<Mutex<MessagesPool>>, subscription_id: u64, username: &Mutex<String>, signal: String) -> Result<()>;
+  fn process_incoming_message(messages_pool: &Mutex<MessagesPool>, subscription_id: u64, username: &str, data: SignalData) -> Result<()>;
+  fn process_direct_message(messages_pool: &Mutex<MessagesPool>, subscription_id: u64, username: &str, data: SignalData) -> Result<()>;
 }
 
 impl DataManager for Manager {
-  fn deny_auth(&mut self) -> Result<()> {
+  async fn deny_auth(&mut self, error: &AuthConnectionError) -> Result<()> {
     let response = SignalData::new(
-      vec![SignalHeader::AuthStatus(AuthStatus::DENIED)],
-      None
+      vec![
+        SignalHeader::SignalType(SignalType::Connection),
+        SignalHeader::AuthStatus(AuthStatus::DENIED),
+        SignalHeader::DenialReason(error.reason),
+        SignalHeader::WithMessage
+      ],
+      Some(&error.text)
     );
 
-    self.send_data(&response.to_string())?;
+    self.send_data(&response.to_string()).await?;
     Ok(())
   }
 
-  fn auth(&mut self, signal: String) -> Result<()> {
-    let data = SignalData::from_str(&signal)?;
+  /// Возвращает принятый сигнал подключения
+  async fn auth(&mut self, signal: String) -> Result<SignalData> {
+    let data = SignalData::from_str(&signal).map_err(|_| {
+      AuthConnectionError::new(DenialReason::InvalidSignal, "Invalid connection signal")
+    })?;
 
     match data.signal_type.unwrap() {
-        SignalType::Connection => {
-          if let None = data.username {
-            return Err(AuthConnectionError.into());
-          }
-          let mut state = self.state.get();
-          if state.users.contains_key(&data.username.clone().unwrap()) {
-            return Err(AuthConnectionError.into())
-          }
-          state.users.insert(data.username.clone().unwrap().to_owned(), UserData {
-            address: self.stream.peer_addr()?.to_string(),
-          });
-          self.messages_pool.lock().push(PoolMessage {
-            id: Uuid::new_v4().to_string(),
-            username: String::new(),
-            message: format!("{} joined the chat!", data.username.clone().unwrap()),
-            from_server: true
-          });
-        }
-        _ => return Err(AuthConnectionError.into()),
+        SignalType::Connection => self.state.get().add_user(&data, &self.connected_peer_addr)?,
+        _ => return Err(AuthConnectionError::new(
+          DenialReason::InvalidSignal,
+          "Expected a connection signal"
+        ).into()),
     }
 
-    self.connected_user_username = Some(data.username.unwrap());
+    self.connected_user_username = data.username.clone().map(|v| Arc::new(Mutex::new(v)));
 
     let response = SignalData::new(
-      vec![SignalHeader::AuthStatus(AuthStatus::ACCEPTED)],
+      vec![
+        SignalHeader::SignalType(SignalType::Connection),
+        SignalHeader::AuthStatus(AuthStatus::ACCEPTED)
+      ],
       None
     );
 
-    self.send_data(&response.to_string())?;
+    self.send_data(&response.to_string()).await?;
+    Ok(data)
+  }
+
+  /// Входит в комнату. Новую комнату не открывает, если их уже `max_rooms`
+  fn join_room(messages_pool: &Mutex<MessagesPool>, subscription_id: u64, username: &str, room: &str, query: &HistoryQuery, max_rooms: usize) -> Result<()> {
+    if !is_valid_room_name(room) {
+      return Err(IncomingMessageError(format!("Invalid room name {room}")).into())
+    }
+    let mut pool = messages_pool.lock();
+    if !pool.has_room(room) && pool.room_count() >= max_rooms {
+      return Err(IncomingMessageError(format!("The server already has {max_rooms} rooms, join one of them")).into())
+    }
+    if !pool.join(room, subscription_id, username) {
+      return Err(IncomingMessageError(format!("You are already in {room}")).into())
+    }
+    // История уходит под тем же локом, поэтому новые сообщения придут после нее
+    let history = history_signals(room, pool.history(room, query));
+    pool.send_to(subscription_id, Outgoing::Batch(history));
+    pool.broadcast(room, presence_signal(room, username, PresenceEvent::Joined, vec![]));
     Ok(())
   }
 
-  fn remove_user(&mut self, username: String) -> Result<()> {
-    let mut state = self.state.get();
+  fn send_history(messages_pool: &Mutex<MessagesPool>, subscription_id: u64, room: &str, query: &HistoryQuery) -> Result<()> {
+    let mut pool = messages_pool.lock();
+    if !pool.is_member(room, subscription_id) {
+      return Err(IncomingMessageError(format!("You are not in {room}")).into())
+    }
+    let history = history_signals(room, pool.history(room, query));
+    pool.send_to(subscription_id, Outgoing::Batch(history));
+    Ok(())
+  }
 
-    if state.users.contains_key(&username) {
-      state.users.remove(&username);
-      self.messages_pool.lock().push(PoolMessage {
-        id: Uuid::new_v4().to_string(),
-        username: String::new(),
-        message: format!("{username} left the chat!"),
-        from_server: true
-      });
+  fn part_room(messages_pool: &Mutex<MessagesPool>, subscription_id: u64, username: &str, room: &str) -> Result<()> {
+    if room == DEFAULT_ROOM {
+      return Err(IncomingMessageError(format!("You can't leave {DEFAULT_ROOM}")).into())
     }
+    let mut pool = messages_pool.lock();
+    if !pool.is_member(room, subscription_id) {
+      return Err(IncomingMessageError(format!("You are not in {room}")).into())
+    }
+    // Уведомление уходит до выхода, чтобы его увидел и сам пользователь
+    pool.broadcast(room, presence_signal(room, username, PresenceEvent::Left, vec![]));
+    pool.part(room, subscription_id);
     Ok(())
   }
 
-  fn process_messages_pool(&mut self, receiver: Receiver<()>) -> Result<()> {
-    loop {
-      if let Ok(()) = receiver.try_recv() {
-        break;
-      };
+  fn list_rooms(messages_pool: &Mutex<MessagesPool>, subscription_id: u64) {
+    let mut pool = messages_pool.lock();
+    let rooms = pool.rooms().iter()
+      .map(|(name, members)| format!("{name} {members}"))
+      .collect::<Vec<String>>()
+      .join("\n");
+    let response = SignalData::new(
+      vec![
+        SignalHeader::SignalType(SignalType::List),
+        SignalHeader::WithMessage
+      ],
+      Some(&rooms)
+    );
+    pool.send_to(subscription_id, Outgoing::Signal(Arc::new(response)));
+  }
+
+  /// Кто есть в `room`, по имени на строку
+  fn list_users(messages_pool: &Mutex<MessagesPool>, subscription_id: u64, room: &str) {
+    let mut pool = messages_pool.lock();
+    let users = pool.members(room).join("\n");
+    let response = SignalData::new(
+      vec![
+        SignalHeader::SignalType(SignalType::UserList),
+        SignalHeader::Room(room.to_owned()),
+        SignalHeader::WithMessage
+      ],
+      Some(&users)
+    );
+    pool.send_to(subscription_id, Outgoing::Signal(Arc::new(response)));
+  }
 
-      let lock_ref = self.messages_pool.clone();
-      let pool_lock = lock_ref.lock();
+  /// Проверяет новое имя и переносит пользователя под локом состояния, как
+  /// `auth`, чтобы два соединения не заняли одно имя
+  fn rename_user(state: &State, messages_pool: &Mutex<MessagesPool>, subscription_id: u64, username: &Mutex<String>, data: SignalData) -> Result<()> {
+    let Some(new_username) = data.new_username else {
+      return Err(IncomingMessageError("New username is missing".to_owned()).into())
+    };
+    if !is_valid_username(&new_username) {
+      return Err(IncomingMessageError(format!("Invalid username {new_username}")).into())
+    }
 
-      let messages = pool_lock.has_new(&self.last_read_message_id);
-      if let Some(v) = messages {
-        if let Some(last) = v.1 {
-          self.last_read_message_id = last;
-        }
-        for message in v.0 {
-          let mut syg_vec = vec![
-            SignalHeader::SignalType(SignalType::NewMessage),
-            SignalHeader::Username(message.username.clone()),
-            SignalHeader::WithMessage
-          ];
-          if message.from_server {
-            syg_vec.push(SignalHeader::ServerMessage);
+    let mut state = state.get();
+    let mut username = username.lock();
+    state.rename_user(&username, &new_username)?;
+
+    let mut pool = messages_pool.lock();
+    for room in pool.rename(subscription_id, &new_username) {
+      let details = vec![SignalHeader::NewUsername(new_username.clone())];
+      pool.broadcast(&room, presence_signal(&room, &username, PresenceEvent::Renamed, details));
+    }
+    println!("{username} is now known as {new_username}");
+    *username = new_username;
+    Ok(())
+  }
+
+  fn remove_user(&mut self) -> Result<()> {
+    let mut state = self.state.get();
+    let Some(username) = self.connected_user_username.as_ref().map(|v| v.lock().clone()) else {
+      return Ok(())
+    };
+
+    if state.remove_user(&username) {
+      let Some(subscription_id) = self.subscription_id else {
+        return Ok(())
+      };
+      let mut pool = self.messages_pool.lock();
+      pool.unsubscribe(subscription_id);
+      let (event, details) = match pool.take_kick_reason(subscription_id) {
+        Some(reason) => (PresenceEvent::Kicked, vec![SignalHeader::Reason(reason)]),
+        None => (PresenceEvent::Left, vec![])
+      };
+      for room in pool.leave_all(subscription_id) {
+        pool.broadcast(&room, presence_signal(&room, &username, event, details.clone()));
+      }
+    }
+    Ok(())
+  }
+
+  async fn process_messages_pool(&mut self, mut subscription: Subscription) -> Result<()> {
+    // Задача спит, пока в очередь не придет новый сигнал или пока
+    // соединение не отпишется
+    while let Some(outgoing) = subscription.receiver.recv().await {
+      match outgoing {
+        Outgoing::Signal(v) => self.send_data(&v.to_string()).await?,
+        Outgoing::Batch(v) => {
+          for signal in v {
+            self.send_data(&signal.to_string()).await?;
           }
-          let response = SignalData::new(syg_vec, Some(&message.message));
-          self.send_data(&response.to_string())?;
         }
       }
-      thread::sleep(Duration::from_millis(10));
     }
 
     Ok(())
   }
 
-  fn process_incoming_message(messages_pool: Arc<Mutex<MessagesPool>>, signal: String) -> Result<()> {
+  fn process_incoming_signal(state: &State, messages_pool: Arc<Mutex<MessagesPool>>, subscription_id: u64, username: &Mutex<String>, signal: String) -> Result<()> {
     let data = SignalData::from_str(&signal)?;
-  
-    if !data.with_message || data.username.is_none() {
-      return Err(IncomingMessageError.into())
+    let room = data.room.clone().unwrap_or(DEFAULT_ROOM.to_owned());
+    // Имя может поменяться RENAME, поэтому берем текущее на каждый сигнал
+    let current_username = username.lock().clone();
+
+    let result = match data.signal_type.unwrap() {
+      SignalType::NewMessage => Self::process_incoming_message(&messages_pool, subscription_id, &current_username, data),
+      SignalType::DirectMessage => Self::process_direct_message(&messages_pool, subscription_id, &current_username, data),
+      SignalType::Join => {
+        let max_rooms = state.get().settings.max_rooms;
+        Self::join_room(&messages_pool, subscription_id, &current_username, &room, &HistoryQuery::from_signal(&data), max_rooms)
+      },
+      SignalType::History => Self::send_history(&messages_pool, subscription_id, &room, &HistoryQuery::from_signal(&data)),
+      SignalType::Part => Self::part_room(&messages_pool, subscription_id, &current_username, &room),
+      SignalType::List => {
+        Self::list_rooms(&messages_pool, subscription_id);
+        Ok(())
+      },
+      SignalType::UserList => {
+        Self::list_users(&messages_pool, subscription_id, &room);
+        Ok(())
+      },
+      SignalType::Rename => Self::rename_user(state, &messages_pool, subscription_id, username, data),
+      _ => Err(IncomingMessageError("Unexpected signal".to_owned()).into())
+    };
+
+    // Ошибки запросов возвращаем клиенту, остальное просто логируем
+    if let Err(e) = &result {
+      if let Some(e) = e.downcast_ref::<IncomingMessageError>() {
+        let response = SignalData::new(
+          vec![
+            SignalHeader::SignalType(SignalType::Error),
+            SignalHeader::Room(room),
+            SignalHeader::WithMessage
+          ],
+          Some(&e.0)
+        );
+        messages_pool.lock().send_to(subscription_id, Outgoing::Signal(Arc::new(response)));
+      }
     }
-  
-    messages_pool.lock().push(PoolMessage {
+    result
+  }
+
+  fn process_incoming_message(messages_pool: &Mutex<MessagesPool>, subscription_id: u64, username: &str, data: SignalData) -> Result<()> {
+    if !data.with_message {
+      return Err(IncomingMessageError("Message is empty".to_owned()).into())
+    }
+    check_sender(username, &data)?;
+    let room = data.room.unwrap_or(DEFAULT_ROOM.to_owned());
+
+    let mut pool = messages_pool.lock();
+    if !pool.is_member(&room, subscription_id) {
+      return Err(IncomingMessageError(format!("You are not in {room}")).into())
+    }
+    pool.push(PoolMessage {
       id: Uuid::new_v4().to_string(),
-      username: data.username.clone().unwrap(),
-      message: data.message.clone().unwrap().trim().to_owned(),
-      from_server: false
+      seq: 0,
+      room,
+      username: username.to_owned(),
+      message: data.message.unwrap().trim().to_owned(),
+      from_server: false,
+      timestamp: unix_timestamp()
     });
   
     Ok(())
   }
-}
\ No newline at end of file
+
+  fn process_direct_message(messages_pool: &Mutex<MessagesPool>, subscription_id: u64, username: &str, data: SignalData) -> Result<()> {
+    if data.message.as_deref().is_none_or(|v| v.trim().is_empty()) {
+      return Err(IncomingMessageError("Message is empty".to_owned()).into())
+    }
+    let Some(target) = data.target else {
+      return Err(IncomingMessageError("Direct message has no recipient".to_owned()).into())
+    };
+
+    let mut pool = messages_pool.lock();
+    let Some(target_id) = pool.find_user(&target) else {
+      return Err(IncomingMessageError(format!("{target} is offline")).into())
+    };
+    // Личные сообщения не попадают в историю комнат
+    let message = SignalData::new(
+      vec![
+        SignalHeader::SignalType(SignalType::DirectMessage),
+        SignalHeader::MessageId(Uuid::new_v4().to_string()),
+        SignalHeader::Timestamp(unix_timestamp()),
+        SignalHeader::Username(username.to_owned()),
+        SignalHeader::Target(target),
+        SignalHeader::WithMessage
+      ],
+      Some(data.message.unwrap().trim())
+    );
+    let message = Arc::new(message);
+    pool.send_to(target_id, Outgoing::Signal(message.clone()));
+    // Копия отправителю, чтобы сообщение появилось и у него
+    if target_id != subscription_id {
+      pool.send_to(subscription_id, Outgoing::Signal(message));
+    }
+
+    Ok(())
+  }
+}
@@ -1,37 +1,42 @@
-use std::{
-  net::TcpStream, 
-  io::BufReader, 
-  sync::Arc
-};
+use std::sync::Arc;
 use parking_lot::Mutex;
 use anyhow::Result;
+use tokio::{
+  io::BufReader,
+  net::{
+    TcpStream,
+    tcp::{OwnedReadHalf, OwnedWriteHalf}
+  }
+};
 
 use crate::{state::State, messages_pool::MessagesPool};
-use super::stream_manager::StreamManager;
 
 pub struct Manager {
-  pub stream: TcpStream,
-  pub reader: BufReader<TcpStream>,
+  pub stream: OwnedWriteHalf,
+  /// Забирается читающей задачей после авторизации
+  pub reader: Option<BufReader<OwnedReadHalf>>,
   pub state: State,
   pub messages_pool: Arc<Mutex<MessagesPool>>,
-  pub last_read_message_id: String,
-  pub connected_user_username: Option<String>,
-  pub connected_peer_addr: String
+  /// Общее с читающей задачей, меняется по RENAME под локом состояния
+  pub connected_user_username: Option<Arc<Mutex<String>>>,
+  pub connected_peer_addr: String,
+  /// Id очереди соединения в пуле, появляется после авторизации
+  pub subscription_id: Option<u64>
 }
 
 impl Manager {
-  pub fn new(stream: TcpStream, state: State, messages_pool: Arc<Mutex<MessagesPool>>) -> Result<()> {
-    let mut manager = Manager {
-      stream: stream.try_clone()?,
-      reader: BufReader::new(stream.try_clone()?),
+  pub fn new(stream: TcpStream, state: State, messages_pool: Arc<Mutex<MessagesPool>>) -> Result<Manager> {
+    let connected_peer_addr = stream.peer_addr()?.to_string();
+    let (reader, writer) = stream.into_split();
+
+    Ok(Manager {
+      stream: writer,
+      reader: Some(BufReader::new(reader)),
       state,
       messages_pool,
-      last_read_message_id: String::new(),
       connected_user_username: None,
-      connected_peer_addr: stream.try_clone()?.peer_addr()?.to_string()
-    };
-
-    manager.process_connection()?;
-    Ok(())
+      connected_peer_addr,
+      subscription_id: None
+    })
   }
-}
\ No newline at end of file
+}
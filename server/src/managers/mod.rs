@@ -2,4 +2,5 @@ mod manager;
 mod stream_manager;
 mod data_manager;
 
-pub use manager::Manager;
\ No newline at end of file
+pub use manager::Manager;
+pub(crate) use stream_manager::StreamManager;
\ No newline at end of file
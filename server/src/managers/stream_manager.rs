@@ -1,93 +1,115 @@
-use std::{ 
-  io::{
-    Write, BufReader
-  }, 
-  thread,
-  sync::mpsc::{
-    self, 
-    Sender
-  }
+use std::time::Duration;
+use anyhow::{anyhow, Result};
+use tokio::{
+  io::AsyncWriteExt,
+  task::JoinHandle,
+  time
 };
-use anyhow::Result;
 
-use crate::{managers::data_manager::DataManager, reader::StreamReader};
+use tchat_protocol::{AsyncSignalReader, AuthConnectionError, DenialReason, DEFAULT_ROOM};
+
+use crate::{managers::data_manager::DataManager, messages_pool::HistoryQuery};
 
 use super::manager::Manager;
 
+/// Клиент, который столько времени не может принять данные, отключается
+const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Столько ждем сигнала подключения, чтобы молчащий сокет не висел вечно
+const AUTH_TIMEOUT: Duration = Duration::from_secs(10);
+
 pub trait StreamManager {
-  fn process_connection(&mut self) -> Result<()>;
-  fn process_disconnection(&mut self) -> Result<()>;
-  fn send_data(&mut self, data: &str) -> Result<()>;
-  fn process_signals(&mut self, sender: Sender<()>) -> Result<()>;
+  async fn process_connection(&mut self) -> Result<()>;
+  async fn process_session(&mut self) -> Result<()>;
+  async fn process_disconnection(&mut self) -> Result<()>;
+  async fn send_data(&mut self, data: &str) -> Result<()>;
+  fn process_signals(&mut self, subscription_id: u64) -> Result<JoinHandle<()>>;
 }
 
 impl StreamManager for Manager {
-  fn process_connection(&mut self) -> Result<()> {
+  async fn process_connection(&mut self) -> Result<()> {
     println!("Connection established - {}", self.connected_peer_addr);
 
-    let auth_data = match BufReader::new(
-      self.stream.try_clone()?
-    ).read_signal() {
+    // Уборка идет при любом выходе, иначе имя останется занятым навсегда
+    let result = self.process_session().await;
+    self.process_disconnection().await?;
+    result
+  }
+
+  async fn process_session(&mut self) -> Result<()> {
+    let auth_data = match self.reader.as_mut() {
+      Some(reader) => time::timeout(AUTH_TIMEOUT, reader.read_signal()).await,
+      None => return Err(anyhow!("connection reader is already taken"))
+    };
+    let Ok(Ok(auth_data)) = auth_data else {
+      return Ok(())
+    };
+
+    let connection_signal = match self.auth(auth_data).await {
       Ok(v) => v,
-      Err(_) => {
-        self.process_disconnection()?;
+      Err(e) => {
+        let error = match e.downcast::<AuthConnectionError>() {
+          Ok(v) => v,
+          Err(_) => AuthConnectionError::new(DenialReason::InvalidSignal, "Login failed on the server")
+        };
+        self.deny_auth(&error).await?;
         return Ok(())
       }
     };
 
-    if self.auth(auth_data.clone()).is_err() {
-      self.deny_auth()?;
-      self.process_disconnection()?;
-      return Ok(())
-    }
-
-    let (channel_sender, channel_receiver) = mpsc::channel::<()>();
-    self.process_signals(channel_sender)?;
-    
-    self.process_messages_pool(channel_receiver)?;
-
-    self.process_disconnection()?;
-    Ok(())
+    let username = self.connected_user_username.as_ref()
+      .map(|v| v.lock().clone())
+      .unwrap_or_default();
+    let subscription = self.messages_pool.lock().subscribe(&username);
+    let subscription_id = subscription.id;
+    self.subscription_id = Some(subscription_id);
+    let history = HistoryQuery::from_signal(&connection_signal);
+    let max_rooms = self.state.get().settings.max_rooms;
+    Self::join_room(&self.messages_pool, subscription_id, &username, DEFAULT_ROOM, &history, max_rooms)?;
+    let reader_task = self.process_signals(subscription_id)?;
+
+    let result = self.process_messages_pool(subscription).await;
+
+    reader_task.abort();
+    result
   }
 
-  fn process_disconnection(&mut self) -> Result<()> {
+  async fn process_disconnection(&mut self) -> Result<()> {
     if self.connected_user_username.is_some() {
-      self.remove_user(self.connected_user_username.clone().unwrap())?;
+      self.remove_user()?;
     }
+    self.stream.shutdown().await.ok();
     println!("Connection closed - {}", self.connected_peer_addr);
     Ok(())
   }
 
-  fn send_data(&mut self, data: &str) -> Result<()> {
-    self.stream.write(data.as_bytes())?;
+  async fn send_data(&mut self, data: &str) -> Result<()> {
+    time::timeout(WRITE_TIMEOUT, self.stream.write_all(data.as_bytes())).await??;
     Ok(())
   }
 
-  fn process_signals(&mut self, sender: Sender<()>) -> Result<()> {
-    let cloned_stream = self.stream.try_clone()?;
+  fn process_signals(&mut self, subscription_id: u64) -> Result<JoinHandle<()>> {
+    let mut reader = self.reader.take()
+      .ok_or_else(|| anyhow!("connection reader is already taken"))?;
+    let cloned_state = self.state.clone();
     let cloned_messages_pool = self.messages_pool.clone();
-
-    thread::spawn(move || -> Result<()> {
-      let mut reader = BufReader::new(cloned_stream.try_clone()?);
-      loop {
-        let data_from_socket = match reader.read_signal() {
-          Ok(s) => s,
-          Err(_) => {
-            break;
-          }
-        };
-
-        match Self::process_incoming_message(cloned_messages_pool.clone(), data_from_socket) {
-          Ok(_) => (),
-          Err(_) => println!("invalid message")
-        };
+    let username = self.connected_user_username.clone()
+      .ok_or_else(|| anyhow!("connection is not authorized"))?;
+
+    Ok(tokio::spawn(async move {
+      while let Ok(data_from_socket) = reader.read_signal().await {
+        if let Err(e) = Self::process_incoming_signal(
+          &cloned_state,
+          cloned_messages_pool.clone(),
+          subscription_id,
+          &username,
+          data_from_socket
+        ) {
+          println!("Invalid signal from {}: {e}", username.lock());
+        }
       }
 
-      sender.send(())?;
-
-      Ok(())
-    });
-
-    Ok(())
+      // Очередь закроется, и пишущая сторона завершит соединение
+      cloned_messages_pool.lock().unsubscribe(subscription_id);
+    }))
   }
-}
\ No newline at end of file
+}
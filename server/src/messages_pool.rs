@@ -1,111 +1,390 @@
-use std::{collections::{HashMap, VecDeque}, iter};
+use std::{
+  collections::{HashMap, VecDeque},
+  io,
+  sync::Arc,
+  time::{SystemTime, UNIX_EPOCH}
+};
+use tokio::sync::mpsc::{
+  self,
+  error::TrySendError,
+  Receiver,
+  Sender
+};
+
+use tchat_protocol::{DEFAULT_HISTORY_LIMIT, DEFAULT_ROOM, SignalData, SignalHeader, SignalType};
+
+use crate::storage::{Storage, StorageWriter};
+
+pub const POOL_SIZE: usize = 256;
+
+/// Секунды с начала эпохи Unix, в UTC
+pub fn unix_timestamp() -> u64 {
+  SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map(|v| v.as_secs())
+    .unwrap_or(0)
+}
 
 #[derive(Debug, Clone)]
 pub struct PoolMessage {
   pub id: String,
+  /// Порядковый номер в комнате, начиная с 1. Назначается пулом в `push`.
+  pub seq: u64,
+  pub room: String,
   pub username: String,
   pub message: String,
   pub from_server: bool,
+  pub timestamp: u64,
 }
 
 impl PoolMessage {
-  fn new() -> PoolMessage {
-    PoolMessage {
-      id: String::new(),
-      username: String::new(),
-      message: String::new(),
-      from_server: false,
+  /// `signal_type` - NEW_MESSAGE для новых сообщений и HISTORY для истории
+  pub fn to_signal(&self, signal_type: SignalType) -> SignalData {
+    let mut syg_vec = vec![
+      SignalHeader::SignalType(signal_type),
+      SignalHeader::Room(self.room.clone()),
+      SignalHeader::Seq(self.seq),
+      SignalHeader::MessageId(self.id.clone()),
+      SignalHeader::Timestamp(self.timestamp),
+      SignalHeader::Username(self.username.clone()),
+      SignalHeader::WithMessage
+    ];
+    if self.from_server {
+      syg_vec.push(SignalHeader::ServerMessage);
+    }
+    SignalData::new(syg_vec, Some(&self.message))
+  }
+}
+
+/// Какую часть истории комнаты просит клиент
+#[derive(Debug, Clone)]
+pub struct HistoryQuery {
+  pub limit: usize,
+  pub since: Option<u64>,
+  /// Номер последнего сообщения, которое клиент уже видел
+  pub after: Option<u64>,
+}
+
+impl HistoryQuery {
+  /// Читает хедеры LIMIT, SINCE и AFTER. Лимит ограничен размером пула комнаты
+  pub fn from_signal(data: &SignalData) -> HistoryQuery {
+    HistoryQuery {
+      limit: data.limit.unwrap_or(DEFAULT_HISTORY_LIMIT).min(POOL_SIZE),
+      since: data.since,
+      after: data.after
     }
   }
 }
 
+/// Ответ на [`HistoryQuery`]
+pub struct History {
+  /// Сообщения после `after`, которых уже нет в пуле или которые не поместились
+  /// в лимит
+  pub skipped: u64,
+  /// AFTER больше последнего номера комнаты: история на сервере началась
+  /// заново, например после перезапуска без --data-dir
+  pub reset: bool,
+  pub messages: Vec<Arc<PoolMessage>>,
+}
+
+/// То, что ждет отправки соединению. История комнаты уходит одной пачкой,
+/// чтобы не забивать очередь.
+pub enum Outgoing {
+  Signal(Arc<SignalData>),
+  Batch(Vec<Arc<SignalData>>),
+}
+
+/// Очередь сигналов, которые ждут отправки одному соединению
+pub struct Subscription {
+  pub id: u64,
+  pub receiver: Receiver<Outgoing>,
+}
+
+#[derive(Default)]
+struct Room {
+  messages: VecDeque<Arc<PoolMessage>>,
+  /// id подписчика -> имя пользователя
+  members: HashMap<u64, String>,
+  /// Номер последнего сообщения комнаты, даже если оно уже вытеснено
+  last_seq: u64,
+}
+
+impl Room {
+  fn remember(&mut self, v: Arc<PoolMessage>) {
+    if self.messages.len() == POOL_SIZE {
+      self.messages.pop_front();
+    }
+    self.last_seq = self.last_seq.max(v.seq);
+    self.messages.push_back(v);
+  }
+}
+
 pub struct MessagesPool {
-  pool: VecDeque<PoolMessage>,
-  indexes: HashMap<String, u8>,
-  length: u16,
+  rooms: HashMap<String, Room>,
+  subscribers: HashMap<u64, Sender<Outgoing>>,
+  /// Имя пользователя -> id его подписки, для личных сообщений
+  usernames: HashMap<String, u64>,
+  /// Отключенные сервером подписчики и причина, пока их не убрали из комнат
+  kicked: HashMap<u64, String>,
+  next_subscriber_id: u64,
+  queue_size: usize,
+  storage: Option<StorageWriter>,
 }
 
 impl MessagesPool {
-  pub fn new() -> MessagesPool {
-    let arr: VecDeque<PoolMessage> = iter::repeat_with(|| PoolMessage::new())
-      .take(256)
-      .collect();
-    MessagesPool { 
-      pool: arr, 
-      indexes: HashMap::new(),
-      length: 0
+  /// `queue_size` - сколько сигналов может ждать отправки одному подписчику,
+  /// прежде чем его отключат как слишком медленного
+  pub fn new(queue_size: usize) -> MessagesPool {
+    let mut rooms = HashMap::new();
+    rooms.insert(DEFAULT_ROOM.to_owned(), Room::default());
+
+    MessagesPool {
+      rooms,
+      subscribers: HashMap::new(),
+      usernames: HashMap::new(),
+      kicked: HashMap::new(),
+      next_subscriber_id: 0,
+      queue_size,
+      storage: None
     }
   }
 
-  pub fn push(&mut self, v: PoolMessage) {
-    if self.length == 256 {
-      self.pool.pop_front();
-      self.pool.push_back(v);
-
-      let mut new_indexes: HashMap<String, u8> = HashMap::new();
-      for (index, message) in self.pool.iter().enumerate() {
-        new_indexes.insert(message.id.clone(), index as u8);
+  /// То же, что `new`, но сообщения еще пишутся в `storage`. Сразу
+  /// восстанавливается только лобби, остальные комнаты - когда в них входят
+  pub fn with_storage(queue_size: usize, mut storage: Storage) -> io::Result<MessagesPool> {
+    let mut pool = MessagesPool::new(queue_size);
+    for v in storage.compact()? {
+      if let Some(room) = pool.rooms.get_mut(&v.room) {
+        room.remember(Arc::new(v));
       }
-      self.indexes = new_indexes;
-    }
-    else {
-      let index = self.length as u8;
-      self.pool[index as usize] = v.clone();
-      self.length += 1;
-      self.indexes.insert(v.id.clone(), index);
-    }
-  }
-
-  fn read_from(&self, id: &str) -> (Vec<PoolMessage>, Option<String>) {
-    let found_index = self.indexes.get(id);
-    match found_index {
-      Some(v) => {
-        let index: u16 = v.to_owned() as u16 + 1;
-        let sliced_pool = &Vec::from(self.pool.clone())[index.into()..self.length.into()];
-        let sliced_pool_last = {
-          if sliced_pool.len() == 0 {
-            None
-          }
-          else {
-            Some(sliced_pool.last().unwrap().clone().id)
-          }
-        };
-        return (sliced_pool.clone().into(), sliced_pool_last)
+    }
+    pool.storage = Some(StorageWriter::spawn(storage)?);
+    Ok(pool)
+  }
+
+  /// Кладет сообщение в пул комнаты и сразу раздает его ее участникам.
+  /// Если комнаты нет, сообщение никуда не попадет.
+  pub fn push(&mut self, mut v: PoolMessage) {
+    let Some(room) = self.rooms.get_mut(&v.room) else {
+      return
+    };
+    v.seq = room.last_seq + 1;
+    let v = Arc::new(v);
+    room.remember(v.clone());
+    // Пишет отдельный поток, лок пула диска не ждет
+    if let Some(storage) = &self.storage {
+      storage.append(v.clone());
+    }
+
+    let room = v.room.clone();
+    self.broadcast(&room, Arc::new(v.to_signal(SignalType::NewMessage)));
+  }
+
+  /// Отправляет сигнал всем в комнате, не сохраняя его в истории
+  pub fn broadcast(&mut self, room: &str, signal: Arc<SignalData>) {
+    let Some(room) = self.rooms.get(room) else {
+      return
+    };
+    let members: Vec<u64> = room.members.keys().copied().collect();
+    for id in members {
+      self.send_to(id, Outgoing::Signal(signal.clone()));
+    }
+  }
+
+  /// Отправляет сигнал одному подписчику. Никого не ждем: подписчик с
+  /// переполненной очередью отключается, с закрытой - больше не нужен.
+  pub fn send_to(&mut self, id: u64, outgoing: Outgoing) {
+    let Some(sender) = self.subscribers.get(&id) else {
+      return
+    };
+    match sender.try_send(outgoing) {
+      Ok(()) => {},
+      Err(TrySendError::Full(_)) => {
+        println!("Subscriber {id} is too slow, disconnecting");
+        self.kick(id, "Too slow to receive messages");
       },
-      None => {
-        let last_el = self.last();
-        let index = match last_el {
-          Some(v) => Some(v.id.clone()),
-          None => None
-        };
-        let sliced_pool = &Vec::from(self.pool.clone())[..self.length.into()];
-        return (sliced_pool.into(), index)
+      Err(TrySendError::Closed(_)) => {
+        self.unsubscribe(id);
       }
     }
   }
 
-  pub fn has_new(&self, id: &str) -> Option<(Vec<PoolMessage>, Option<String>)> {
-    let last_el = self.last();
-    match last_el {
-      Some(_) => Some(self.read_from(id)),
-      None => None,
-    }
+  pub fn subscribe(&mut self, username: &str) -> Subscription {
+    let (sender, receiver) = mpsc::channel::<Outgoing>(self.queue_size);
+    let id = self.next_subscriber_id;
+    self.next_subscriber_id += 1;
+    self.subscribers.insert(id, sender);
+    self.usernames.insert(username.to_owned(), id);
+
+    Subscription { id, receiver }
   }
 
-  fn last(&self) -> Option<PoolMessage> {
-    let last_index = {
-      if self.length > 0 {
-        self.length - 1
-      } else {
-        self.length
+  /// После отписки очередь подписчика закроется. Из комнат он при этом
+  /// не выходит, для этого есть `leave_all`.
+  pub fn unsubscribe(&mut self, id: u64) {
+    self.subscribers.remove(&id);
+    self.usernames.retain(|_, v| *v != id);
+  }
+
+  /// Отключает подписчика. Причина хранится до `take_kick_reason`, чтобы
+  /// остальным можно было сказать, почему пользователь вышел
+  pub fn kick(&mut self, id: u64, reason: &str) {
+    self.kicked.insert(id, reason.to_owned());
+    self.unsubscribe(id);
+  }
+
+  pub fn take_kick_reason(&mut self, id: u64) -> Option<String> {
+    self.kicked.remove(&id)
+  }
+
+  /// Id подписки пользователя, если он сейчас в сети
+  pub fn find_user(&self, username: &str) -> Option<u64> {
+    self.usernames.get(username).copied()
+  }
+
+  /// Меняет имя подписчика в индексе пользователей и во всех его комнатах.
+  /// Возвращает эти комнаты. Свободно ли имя, проверяет вызывающий
+  pub fn rename(&mut self, id: u64, username: &str) -> Vec<String> {
+    self.usernames.retain(|_, v| *v != id);
+    if self.subscribers.contains_key(&id) {
+      self.usernames.insert(username.to_owned(), id);
+    }
+    let mut rooms = Vec::new();
+    for (name, room) in self.rooms.iter_mut() {
+      if let Some(v) = room.members.get_mut(&id) {
+        *v = username.to_owned();
+        rooms.push(name.clone());
       }
+    }
+    rooms.sort();
+    rooms
+  }
+
+  /// Убирает подписчика из всех комнат и возвращает их названия
+  pub fn leave_all(&mut self, id: u64) -> Vec<String> {
+    let rooms: Vec<String> = self.rooms.iter()
+      .filter(|(_, room)| room.members.contains_key(&id))
+      .map(|(name, _)| name.clone())
+      .collect();
+    for name in &rooms {
+      self.remove_member(name, id);
+    }
+    rooms
+  }
+
+  /// Добавляет подписчика в комнату, создавая ее при необходимости.
+  /// Возвращает false, если он уже в ней.
+  pub fn join(&mut self, room: &str, id: u64, username: &str) -> bool {
+    if !self.rooms.contains_key(room) {
+      let restored = self.restore(room);
+      self.rooms.insert(room.to_owned(), restored);
+    }
+    let room = self.rooms.get_mut(room).unwrap();
+    if room.members.contains_key(&id) {
+      return false
+    }
+    room.members.insert(id, username.to_owned());
+    true
+  }
+
+  /// Последние сообщения комнаты, подходящие под запрос, от старых к новым
+  pub fn history(&self, room: &str, query: &HistoryQuery) -> History {
+    let Some(room) = self.rooms.get(room) else {
+      return History { skipped: 0, reset: false, messages: Vec::new() }
+    };
+    // После сброса номера начались заново, отдаем историю как без AFTER
+    let reset = query.after.is_some_and(|after| after > room.last_seq);
+    let after = match reset {
+      true => 0,
+      false => query.after.unwrap_or(0)
+    };
+    let messages: Vec<Arc<PoolMessage>> = room.messages.iter()
+      .filter(|v| v.seq > after)
+      .filter(|v| query.since.is_none_or(|since| v.timestamp >= since))
+      .cloned()
+      .collect();
+    let skip = messages.len().saturating_sub(query.limit);
+    let messages: Vec<Arc<PoolMessage>> = messages.into_iter().skip(skip).collect();
+
+    // Пропуск считаем только от AFTER: все, что между ним и первым
+    // отданным сообщением, клиент уже не получит
+    let skipped = match query.after {
+      Some(after) if !reset => {
+        let next = messages.first().map(|v| v.seq).unwrap_or(room.last_seq + 1);
+        next.saturating_sub(after + 1)
+      },
+      _ => 0
     };
+    History { skipped, reset, messages }
+  }
+
+  /// Возвращает false, если подписчика не было в комнате
+  pub fn part(&mut self, room: &str, id: u64) -> bool {
+    if !self.is_member(room, id) {
+      return false
+    }
+    self.remove_member(room, id);
+    true
+  }
+
+  /// Имена участников комнаты по алфавиту
+  pub fn members(&self, room: &str) -> Vec<String> {
+    let mut members: Vec<String> = self.rooms.get(room)
+      .map(|v| v.members.values().cloned().collect())
+      .unwrap_or_default();
+    members.sort();
+    members
+  }
+
+  pub fn is_member(&self, room: &str, id: u64) -> bool {
+    self.rooms.get(room).is_some_and(|v| v.members.contains_key(&id))
+  }
+
+  /// Есть ли комната сейчас. Пустых комнат, кроме лобби, нет, даже если их
+  /// история сохранена
+  pub fn has_room(&self, room: &str) -> bool {
+    self.rooms.contains_key(room)
+  }
+
+  pub fn room_count(&self) -> usize {
+    self.rooms.len()
+  }
+
+  /// Комнаты и количество участников в них, по алфавиту
+  pub fn rooms(&self) -> Vec<(String, usize)> {
+    let mut rooms: Vec<(String, usize)> = self.rooms.iter()
+      .map(|(name, room)| (name.clone(), room.members.len()))
+      .collect();
+    rooms.sort();
+    rooms
+  }
 
-    let last_el = &self.pool[last_index.into()];
-    if last_el.id == "".to_owned() {
-      None
-    } else {
-      Some(last_el.to_owned())
+  /// Комната с сохраненной историей или новая пустая. Читает диск под локом
+  /// пула, но только когда входят в комнату, которой сейчас нет
+  fn restore(&self, name: &str) -> Room {
+    let mut room = Room::default();
+    let Some(storage) = &self.storage else {
+      return room
+    };
+    match storage.load_room(name) {
+      Ok(messages) => {
+        for v in messages {
+          room.remember(Arc::new(v));
+        }
+      },
+      Err(e) => println!("Failed to load the history of {name}: {e}")
     }
+    room
   }
-}
\ No newline at end of file
+
+  fn remove_member(&mut self, room: &str, id: u64) {
+    if let Some(v) = self.rooms.get_mut(room) {
+      v.members.remove(&id);
+      // Пустые комнаты, кроме лобби, удаляем из памяти. Сохраненная история
+      // остается на диске и читается снова, когда в комнату войдут
+      if v.members.is_empty() && room != DEFAULT_ROOM {
+        self.rooms.remove(room);
+      }
+    }
+  }
+}
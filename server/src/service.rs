@@ -1,29 +1,64 @@
-use std::{net::TcpListener, thread, sync::Arc};
+use std::{
+  io::{self, ErrorKind},
+  sync::Arc,
+  time::Duration
+};
 use anyhow::Result;
 use parking_lot::Mutex;
+use tokio::{net::TcpListener, time};
 
-use crate::{state::State, managers::Manager, messages_pool::MessagesPool};
+use crate::{
+  state::State,
+  managers::{Manager, StreamManager},
+  messages_pool::MessagesPool,
+  storage::Storage
+};
+
+/// Пауза после ошибки accept, например когда кончились дескрипторы
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Ошибка самого сокета, а не одного соединения. Слушать дальше бессмысленно
+fn is_listener_error(e: &io::Error) -> bool {
+  e.kind() == ErrorKind::InvalidInput
+}
 
 pub struct Service;
 
 impl Service {
-  pub fn run(state: State) -> Result<()> {
-    let listener = TcpListener::bind(format!("0.0.0.0:{}", state.get().settings.port))?;
+  pub async fn run(state: State) -> Result<()> {
+    let settings = state.get().settings.clone();
+    let port = settings.port;
+    let listener = TcpListener::bind(format!("0.0.0.0:{port}")).await?;
 
     println!("Running!");
 
-    let messages_pool = Arc::new(Mutex::new(MessagesPool::new()));
+    let messages_pool = match &settings.data_dir {
+      Some(v) => {
+        let storage = Storage::open(v, settings.retention.clone())?;
+        MessagesPool::with_storage(settings.send_queue_size, storage)?
+      },
+      None => MessagesPool::new(settings.send_queue_size)
+    };
+    let messages_pool = Arc::new(Mutex::new(messages_pool));
 
-    for con in listener.incoming() {
+    loop {
+      let con = match listener.accept().await {
+        Ok((v, _)) => v,
+        Err(e) if is_listener_error(&e) => return Err(e.into()),
+        Err(e) => {
+          println!("Failed to accept a connection: {e}");
+          // Клиент сам оборвал соединение, ждать незачем
+          if !matches!(e.kind(), ErrorKind::ConnectionAborted | ErrorKind::ConnectionReset) {
+            time::sleep(ACCEPT_BACKOFF).await;
+          }
+          continue;
+        }
+      };
       let cloned_state = state.clone();
       let cloned_messages_pool = messages_pool.clone();
-      thread::spawn(move || -> Result<()> {
-        Manager::new(con?, cloned_state, cloned_messages_pool)?;
-
-        Ok(())
+      tokio::spawn(async move {
+        Manager::new(con, cloned_state, cloned_messages_pool)?.process_connection().await
       });
     }
-
-    Ok(())
   }
-}
\ No newline at end of file
+}
@@ -1,5 +1,8 @@
+use std::{path::PathBuf, time::Duration};
 use clap::Parser;
 
+use crate::{messages_pool::POOL_SIZE, storage::Retention};
+
 #[derive(Parser)]
 pub struct Args {
   #[arg(short, long, help = "Port that the server will serve")]
@@ -10,6 +13,21 @@ pub struct Args {
 
   #[arg(short, long, help = "The key that users need to know to participate the chat")]
   pub key: Option<String>,
+
+  #[arg(long, help = "Maximum amount of rooms, the lobby included")]
+  pub max_rooms: Option<usize>,
+
+  #[arg(long, help = "How many messages may wait to be sent to a client before it is disconnected")]
+  pub send_queue_size: Option<usize>,
+
+  #[arg(long, help = "Directory where the message history is stored. Without it the history is kept in memory only")]
+  pub data_dir: Option<PathBuf>,
+
+  #[arg(long, help = "How many messages per room are kept in the stored history")]
+  pub retention_count: Option<usize>,
+
+  #[arg(long, help = "How many hours messages are kept in the stored history")]
+  pub retention_hours: Option<u64>,
 }
 
 #[derive(Debug, Clone)]
@@ -17,16 +35,29 @@ pub struct Settings {
   pub port: u16,
   pub max_users: u16,
   pub key: Option<String>,
+  pub max_rooms: usize,
+  pub send_queue_size: usize,
+  pub data_dir: Option<PathBuf>,
+  pub retention: Retention,
 }
 
 impl Settings {
+  // Настройки берутся из аргументов командной строки, Default тут не к месту
+  #[allow(clippy::new_without_default)]
   pub fn new() -> Settings {
     let args = Args::parse();
     
     Settings { 
       port: args.port, 
       max_users: args.max_users.unwrap_or(10), 
-      key: args.key
+      key: args.key,
+      max_rooms: args.max_rooms.unwrap_or(100),
+      send_queue_size: args.send_queue_size.unwrap_or(128),
+      data_dir: args.data_dir,
+      retention: Retention {
+        max_messages: args.retention_count.unwrap_or(POOL_SIZE),
+        max_age: args.retention_hours.map(|v| Duration::from_secs(v * 60 * 60))
+      }
     }
   }
 }
@@ -4,19 +4,106 @@ use std::{
 };
 use parking_lot::{Mutex, MutexGuard};
 
-use crate::settings::Settings;
+use tchat_protocol::{
+  PROTOCOL_VERSION,
+  is_valid_username,
+  AuthConnectionError,
+  DenialReason,
+  SignalData
+};
+
+use crate::{errors::IncomingMessageError, settings::Settings};
 
 #[derive(Debug, Clone)]
 pub struct UserData {
   pub address: String,
 }
 
+/// Отправителя берем из авторизации, хедеру USERNAME не доверяем. Если он
+/// есть, он должен совпадать с именем соединения
+pub fn check_sender(username: &str, data: &SignalData) -> Result<(), IncomingMessageError> {
+  match data.username.as_deref() {
+    Some(claimed) if claimed != username => {
+      println!("{username} tried to send a message as {claimed}");
+      Err(IncomingMessageError("Username doesn't match the connection".to_owned()))
+    },
+    _ => Ok(())
+  }
+}
+
 #[derive(Debug, Clone)]
 pub struct StateData {
   pub settings: Settings,
   pub users: HashMap<String, UserData>,
 }
 
+impl StateData {
+  /// Проверяет сигнал подключения и добавляет пользователя. Проверка и
+  /// вставка идут под одним локом, поэтому лимит не превысить
+  pub fn add_user(&mut self, data: &SignalData, address: &str) -> Result<(), AuthConnectionError> {
+    if data.protocol_version != Some(PROTOCOL_VERSION) {
+      return Err(AuthConnectionError::new(
+        DenialReason::VersionMismatch,
+        &format!("Protocol version mismatch, the server speaks version {PROTOCOL_VERSION}")
+      ));
+    }
+    let username = match data.username.as_deref() {
+      Some(v) if is_valid_username(v) => v,
+      _ => return Err(AuthConnectionError::new(
+        DenialReason::InvalidUsername,
+        "Invalid username"
+      ))
+    };
+    if let Some(key) = &self.settings.key {
+      if data.key.as_ref() != Some(key) {
+        return Err(AuthConnectionError::new(
+          DenialReason::BadKey,
+          "Invalid server key"
+        ));
+      }
+    }
+    if self.users.contains_key(username) {
+      return Err(AuthConnectionError::new(
+        DenialReason::UsernameTaken,
+        "Username is already taken"
+      ))
+    }
+    let max_users = self.settings.max_users as usize;
+    if self.users.len() >= max_users {
+      return Err(AuthConnectionError::new(
+        DenialReason::ServerFull,
+        &format!("Server is full ({}/{max_users})", self.users.len())
+      ))
+    }
+    self.users.insert(username.to_owned(), UserData {
+      address: address.to_owned(),
+    });
+    Ok(())
+  }
+
+  /// Освобождает имя и место на сервере. Возвращает false, если такого
+  /// пользователя уже нет
+  pub fn remove_user(&mut self, username: &str) -> bool {
+    self.users.remove(username).is_some()
+  }
+
+  /// Переносит пользователя под новое имя. Занятое имя не отдается, так же
+  /// как при авторизации
+  pub fn rename_user(&mut self, username: &str, new_username: &str) -> Result<(), IncomingMessageError> {
+    if username == new_username {
+      return Err(IncomingMessageError(format!("You are already {new_username}")))
+    }
+    if self.users.contains_key(new_username) {
+      return Err(IncomingMessageError(format!("Username {new_username} is already taken")))
+    }
+    let Some(user) = self.users.remove(username) else {
+      return Err(IncomingMessageError("You are not connected".to_owned()))
+    };
+    self.users.insert(new_username.to_owned(), user);
+    Ok(())
+  }
+}
+
 pub struct State(Arc<Mutex<StateData>>);
 
 impl State {
@@ -29,7 +116,7 @@ impl State {
     )
   }
 
-  pub fn get(&self) -> MutexGuard<StateData> {
+  pub fn get(&self) -> MutexGuard<'_, StateData> {
     self.0.lock()
   }
 }
@@ -0,0 +1,255 @@
+use std::{
+  collections::HashMap,
+  fs::{self, File, OpenOptions},
+  io::{self, BufRead, BufReader, BufWriter, Write},
+  path::{Path, PathBuf},
+  sync::{mpsc, Arc},
+  thread,
+  time::Duration
+};
+
+use crate::messages_pool::{unix_timestamp, PoolMessage};
+
+const LOG_FILE: &str = "messages.log";
+/// Через столько записей лог переписывается с учетом политики хранения
+const COMPACT_EVERY: usize = 1024;
+
+/// Сколько хранятся сохраненные сообщения
+#[derive(Debug, Clone)]
+pub struct Retention {
+  /// Сколько сообщений хранится на комнату, более старые удаляются
+  pub max_messages: usize,
+  pub max_age: Option<Duration>,
+}
+
+/*
+  Лог - это записи подряд, каждая из строки с полями через пробел и текста
+  сообщения указанной длины:
+  <timestamp> <seq> <from_server> <id> <room> <username> <length>\n<message>\n
+
+  Комнаты и имена не содержат пробелов, у серверных сообщений вместо
+  имени пишется "-".
+*/
+
+/// Лог сообщений в каталоге данных сервера, только дописывается
+pub struct Storage {
+  path: PathBuf,
+  file: BufWriter<File>,
+  retention: Retention,
+  appended: usize,
+}
+
+impl Storage {
+  pub fn open(data_dir: &Path, retention: Retention) -> io::Result<Storage> {
+    fs::create_dir_all(data_dir)?;
+    let path = data_dir.join(LOG_FILE);
+    let file = BufWriter::new(open_for_append(&path)?);
+
+    Ok(Storage { path, file, retention, appended: 0 })
+  }
+
+  pub fn append(&mut self, message: &PoolMessage) -> io::Result<()> {
+    write_record(&mut self.file, message)?;
+    self.file.flush()?;
+
+    self.appended += 1;
+    if self.appended >= COMPACT_EVERY {
+      self.compact()?;
+    }
+    Ok(())
+  }
+
+  /// Удаляет сообщения вне политики хранения, переписывает лог и возвращает
+  /// оставшиеся, от старых к новым
+  pub fn compact(&mut self) -> io::Result<Vec<PoolMessage>> {
+    let messages = self.retain(self.load()?);
+
+    // Пишем во временный файл, чтобы при падении не потерять старый лог
+    let tmp_path = self.path.with_extension("tmp");
+    let mut tmp = BufWriter::new(File::create(&tmp_path)?);
+    for message in &messages {
+      write_record(&mut tmp, message)?;
+    }
+    tmp.flush()?;
+    drop(tmp);
+    fs::rename(&tmp_path, &self.path)?;
+
+    self.file = BufWriter::new(open_for_append(&self.path)?);
+    self.appended = 0;
+    Ok(messages)
+  }
+
+  /// Сохраненные сообщения одной комнаты с учетом политики хранения, от
+  /// старых к новым
+  pub fn load_room(&self, room: &str) -> io::Result<Vec<PoolMessage>> {
+    let messages = self.retain(self.load()?);
+    Ok(messages.into_iter().filter(|v| v.room == room).collect())
+  }
+
+  fn load(&self) -> io::Result<Vec<PoolMessage>> {
+    let mut reader = BufReader::new(File::open(&self.path)?);
+    let mut messages = Vec::new();
+    loop {
+      match read_record(&mut reader) {
+        Ok(Some(v)) => messages.push(v),
+        Ok(None) => break,
+        // Обрезанная запись в конце остается после падения сервера
+        Err(e) => {
+          println!("Message log is damaged after {} messages: {e}", messages.len());
+          break
+        }
+      }
+    }
+    Ok(messages)
+  }
+
+  fn retain(&self, messages: Vec<PoolMessage>) -> Vec<PoolMessage> {
+    let oldest = self.retention.max_age
+      .map(|v| unix_timestamp().saturating_sub(v.as_secs()))
+      .unwrap_or(0);
+    let messages: Vec<PoolMessage> = messages.into_iter()
+      .filter(|v| v.timestamp >= oldest)
+      .collect();
+
+    let mut per_room: HashMap<&str, usize> = HashMap::new();
+    for message in &messages {
+      *per_room.entry(&message.room).or_default() += 1;
+    }
+    let mut skip: HashMap<String, usize> = per_room.into_iter()
+      .map(|(room, count)| (room.to_owned(), count.saturating_sub(self.retention.max_messages)))
+      .collect();
+
+    messages.into_iter()
+      .filter(|v| match skip.get_mut(&v.room) {
+        Some(count) if *count > 0 => {
+          *count -= 1;
+          false
+        },
+        _ => true
+      })
+      .collect()
+  }
+}
+
+/// Что поток записи делает со следующим заданием
+enum Job {
+  Append(Arc<PoolMessage>),
+  /// Сообщения комнаты, прочитанные после всего, что было в очереди раньше
+  LoadRoom(String, mpsc::Sender<io::Result<Vec<PoolMessage>>>),
+}
+
+/// Пишет лог в отдельном потоке, чтобы запись на диск и сжатие не держали лок
+/// пула. Оставшееся в очереди дописывается при удалении
+pub struct StorageWriter {
+  sender: Option<mpsc::Sender<Job>>,
+  thread: Option<thread::JoinHandle<()>>,
+}
+
+impl StorageWriter {
+  pub fn spawn(mut storage: Storage) -> io::Result<StorageWriter> {
+    let (sender, receiver) = mpsc::channel::<Job>();
+    let thread = thread::Builder::new()
+      .name("storage".to_owned())
+      .spawn(move || {
+        for job in receiver {
+          match job {
+            Job::Append(message) => {
+              if let Err(e) = storage.append(&message) {
+                println!("Failed to store a message: {e}");
+              }
+            },
+            Job::LoadRoom(room, reply) => {
+              reply.send(storage.load_room(&room)).ok();
+            }
+          }
+        }
+      })?;
+
+    Ok(StorageWriter { sender: Some(sender), thread: Some(thread) })
+  }
+
+  /// Ставит сообщение в очередь на запись, не дожидаясь диска
+  pub fn append(&self, message: Arc<PoolMessage>) {
+    if let Some(sender) = &self.sender {
+      sender.send(Job::Append(message)).ok();
+    }
+  }
+
+  /// Читает комнату через ту же очередь, поэтому ее сообщения, которые еще
+  /// ждут записи, тоже попадут в ответ
+  pub fn load_room(&self, room: &str) -> io::Result<Vec<PoolMessage>> {
+    let (reply, receiver) = mpsc::channel();
+    let stopped = || io::Error::other("Storage thread has stopped");
+    self.sender.as_ref()
+      .ok_or_else(stopped)?
+      .send(Job::LoadRoom(room.to_owned(), reply))
+      .map_err(|_| stopped())?;
+    receiver.recv().map_err(|_| stopped())?
+  }
+}
+
+impl Drop for StorageWriter {
+  fn drop(&mut self) {
+    // Закрытая очередь завершит поток, когда он допишет все, что в ней было
+    self.sender.take();
+    if let Some(v) = self.thread.take() {
+      v.join().ok();
+    }
+  }
+}
+
+fn open_for_append(path: &Path) -> io::Result<File> {
+  OpenOptions::new().create(true).append(true).open(path)
+}
+
+fn write_record(writer: &mut impl Write, message: &PoolMessage) -> io::Result<()> {
+  let username = match message.from_server {
+    true => "-",
+    false => &message.username
+  };
+  write!(
+    writer,
+    "{} {} {} {} {} {} {}\n{}\n",
+    message.timestamp,
+    message.seq,
+    message.from_server as u8,
+    message.id,
+    message.room,
+    username,
+    message.message.len(),
+    message.message
+  )
+}
+
+fn read_record(reader: &mut impl BufRead) -> io::Result<Option<PoolMessage>> {
+  let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid record");
+
+  let mut line = String::new();
+  if reader.read_line(&mut line)? == 0 {
+    return Ok(None)
+  }
+  let fields: Vec<&str> = line.split_whitespace().collect();
+  let [timestamp, seq, from_server, id, room, username, length] = fields[..] else {
+    return Err(invalid())
+  };
+  let from_server = from_server == "1";
+  let length: usize = length.parse().map_err(|_| invalid())?;
+
+  // Текст и перевод строки после него
+  let mut body = vec![0; length + 1];
+  reader.read_exact(&mut body)?;
+  body.pop();
+
+  Ok(Some(PoolMessage {
+    id: id.to_owned(),
+    seq: seq.parse().map_err(|_| invalid())?,
+    room: room.to_owned(),
+    username: match from_server {
+      true => String::new(),
+      false => username.to_owned()
+    },
+    message: String::from_utf8(body).map_err(|_| invalid())?,
+    from_server,
+    timestamp: timestamp.parse().map_err(|_| invalid())?
+  }))
+}
@@ -0,0 +1,210 @@
+use std::sync::Arc;
+
+use tchat_protocol::{SignalData, SignalHeader, SignalType, DEFAULT_ROOM};
+use tchat_server::messages_pool::{HistoryQuery, MessagesPool, PoolMessage, POOL_SIZE};
+
+fn pool_with_messages(count: u64) -> MessagesPool {
+  let mut pool = MessagesPool::new(16);
+  for i in 0..count {
+    pool.push(PoolMessage {
+      id: i.to_string(),
+      seq: 0,
+      room: DEFAULT_ROOM.to_owned(),
+      username: "ivan".to_owned(),
+      message: format!("message {i}"),
+      from_server: false,
+      timestamp: 1000 + i
+    });
+  }
+  pool
+}
+
+fn query(limit: usize, after: Option<u64>) -> HistoryQuery {
+  HistoryQuery { limit, since: None, after }
+}
+
+fn seqs(pool: &MessagesPool, query: HistoryQuery) -> (u64, Vec<u64>) {
+  let history = pool.history(DEFAULT_ROOM, &query);
+  (history.skipped, history.messages.iter().map(|v| v.seq).collect())
+}
+
+#[test]
+fn sequence_numbers_grow_per_room() {
+  let pool = pool_with_messages(3);
+
+  assert_eq!(seqs(&pool, query(10, None)), (0, vec![1, 2, 3]));
+}
+
+#[test]
+fn history_returns_latest_messages() {
+  let pool = pool_with_messages(10);
+
+  assert_eq!(seqs(&pool, query(3, None)), (0, vec![8, 9, 10]));
+}
+
+#[test]
+fn history_since_timestamp() {
+  let pool = pool_with_messages(10);
+  let query = HistoryQuery { limit: 100, since: Some(1008), after: None };
+
+  assert_eq!(seqs(&pool, query), (0, vec![9, 10]));
+}
+
+#[test]
+fn history_after_sequence_number() {
+  let pool = pool_with_messages(10);
+
+  assert_eq!(seqs(&pool, query(100, Some(7))), (0, vec![8, 9, 10]));
+  assert_eq!(seqs(&pool, query(100, Some(10))), (0, vec![]));
+}
+
+#[test]
+fn history_limit_reports_skipped_messages() {
+  let pool = pool_with_messages(10);
+
+  assert_eq!(seqs(&pool, query(2, Some(5))), (3, vec![9, 10]));
+}
+
+#[test]
+fn sequence_numbers_keep_growing_after_wrap_around() {
+  let total = POOL_SIZE as u64 + 44;
+  let pool = pool_with_messages(total);
+
+  let (skipped, messages) = seqs(&pool, query(POOL_SIZE, None));
+  assert_eq!(skipped, 0);
+  assert_eq!(messages.len(), POOL_SIZE);
+  assert_eq!(messages.first(), Some(&45));
+  assert_eq!(messages.last(), Some(&total));
+}
+
+#[test]
+fn client_behind_the_ring_gets_a_gap() {
+  let total = POOL_SIZE as u64 + 44;
+  let pool = pool_with_messages(total);
+
+  // Сообщения 11..=44 вытеснены, их клиент уже не получит
+  let (skipped, messages) = seqs(&pool, query(POOL_SIZE, Some(10)));
+  assert_eq!(skipped, 34);
+  assert_eq!(messages.len(), POOL_SIZE);
+  assert_eq!(messages.first(), Some(&45));
+}
+
+#[test]
+fn client_right_at_the_ring_edge_has_no_gap() {
+  let total = POOL_SIZE as u64 + 44;
+  let pool = pool_with_messages(total);
+
+  let (skipped, messages) = seqs(&pool, query(POOL_SIZE, Some(44)));
+  assert_eq!(skipped, 0);
+  assert_eq!(messages.first(), Some(&45));
+}
+
+#[test]
+fn gap_is_reported_without_messages_left() {
+  let pool = pool_with_messages(10);
+
+  assert_eq!(seqs(&pool, query(0, Some(4))), (6, vec![]));
+}
+
+#[test]
+fn client_ahead_of_the_room_gets_a_reset() {
+  let pool = pool_with_messages(3);
+
+  let history = pool.history(DEFAULT_ROOM, &query(10, Some(50)));
+  assert!(history.reset);
+  assert_eq!(history.skipped, 0);
+  let messages: Vec<u64> = history.messages.iter().map(|v| v.seq).collect();
+  assert_eq!(messages, [1, 2, 3]);
+  assert!(!pool.history(DEFAULT_ROOM, &query(10, Some(3))).reset);
+}
+
+#[test]
+fn history_of_unknown_room_is_empty() {
+  let pool = pool_with_messages(3);
+  let history = pool.history("#nowhere", &query(10, Some(1)));
+
+  assert_eq!(history.skipped, 0);
+  assert!(history.messages.is_empty());
+}
+
+#[test]
+fn join_twice_is_rejected() {
+  let mut pool = MessagesPool::new(16);
+  let subscription = pool.subscribe("ivan");
+
+  assert!(pool.join("#rust", subscription.id, "ivan"));
+  assert!(!pool.join("#rust", subscription.id, "ivan"));
+  assert!(pool.is_member("#rust", subscription.id));
+}
+
+#[test]
+fn members_are_sorted_and_leave_with_part() {
+  let mut pool = MessagesPool::new(16);
+  let ivan = pool.subscribe("ivan");
+  let anna = pool.subscribe("anna");
+  pool.join("#rust", ivan.id, "ivan");
+  pool.join("#rust", anna.id, "anna");
+
+  assert_eq!(pool.members("#rust"), vec!["anna", "ivan"]);
+  pool.part("#rust", ivan.id);
+  assert_eq!(pool.members("#rust"), vec!["anna"]);
+  assert!(pool.members("#nowhere").is_empty());
+}
+
+#[test]
+fn rename_updates_rooms_and_user_index() {
+  let mut pool = MessagesPool::new(16);
+  let ivan = pool.subscribe("ivan");
+  let anna = pool.subscribe("anna");
+  pool.join(DEFAULT_ROOM, ivan.id, "ivan");
+  pool.join("#rust", ivan.id, "ivan");
+  pool.join(DEFAULT_ROOM, anna.id, "anna");
+
+  assert_eq!(pool.rename(ivan.id, "vanya"), vec!["#lobby", "#rust"]);
+  assert_eq!(pool.members(DEFAULT_ROOM), vec!["anna", "vanya"]);
+  assert_eq!(pool.members("#rust"), vec!["vanya"]);
+  assert_eq!(pool.find_user("vanya"), Some(ivan.id));
+  assert_eq!(pool.find_user("ivan"), None);
+}
+
+#[test]
+fn broadcast_reaches_members_and_skips_history() {
+  let mut pool = MessagesPool::new(16);
+  let mut ivan = pool.subscribe("ivan");
+  let mut anna = pool.subscribe("anna");
+  pool.join(DEFAULT_ROOM, ivan.id, "ivan");
+  let signal = Arc::new(SignalData::new(vec![SignalHeader::SignalType(SignalType::Presence)], None));
+  pool.broadcast(DEFAULT_ROOM, signal);
+
+  assert!(ivan.receiver.try_recv().is_ok());
+  assert!(anna.receiver.try_recv().is_err());
+  assert!(pool.history(DEFAULT_ROOM, &query(10, None)).messages.is_empty());
+}
+
+#[test]
+fn slow_subscriber_is_kicked_with_reason() {
+  let mut pool = MessagesPool::new(1);
+  let ivan = pool.subscribe("ivan");
+  pool.join(DEFAULT_ROOM, ivan.id, "ivan");
+  let signal = Arc::new(SignalData::new(vec![SignalHeader::SignalType(SignalType::Presence)], None));
+  pool.broadcast(DEFAULT_ROOM, signal.clone());
+  pool.broadcast(DEFAULT_ROOM, signal);
+
+  assert_eq!(pool.find_user("ivan"), None);
+  assert!(pool.take_kick_reason(ivan.id).is_some());
+  assert!(pool.take_kick_reason(ivan.id).is_none());
+}
+
+#[test]
+fn empty_rooms_are_removed_but_not_the_lobby() {
+  let mut pool = MessagesPool::new(16);
+  let ivan = pool.subscribe("ivan");
+  pool.join(DEFAULT_ROOM, ivan.id, "ivan");
+  pool.join("#rust", ivan.id, "ivan");
+  assert_eq!(pool.room_count(), 2);
+
+  pool.leave_all(ivan.id);
+  assert!(!pool.has_room("#rust"));
+  assert!(pool.has_room(DEFAULT_ROOM));
+  assert_eq!(pool.room_count(), 1);
+}
@@ -0,0 +1,121 @@
+use std::time::Duration;
+
+use tchat_protocol::{
+  PROTOCOL_VERSION,
+  DenialReason,
+  SignalData,
+  SignalHeader,
+  SignalType
+};
+use tchat_server::{
+  settings::Settings,
+  state::{check_sender, State, UserData},
+  storage::Retention
+};
+
+fn settings() -> Settings {
+  Settings {
+    port: 0,
+    max_users: 10,
+    key: None,
+    max_rooms: 10,
+    send_queue_size: 16,
+    data_dir: None,
+    retention: Retention { max_messages: 10, max_age: Some(Duration::from_secs(60)) }
+  }
+}
+
+fn connection_signal(username: &str, key: Option<&str>) -> SignalData {
+  let mut headers = vec![
+    SignalHeader::SignalType(SignalType::Connection),
+    SignalHeader::ProtocolVersion(PROTOCOL_VERSION),
+    SignalHeader::Username(username.to_owned())
+  ];
+  if let Some(key) = key {
+    headers.push(SignalHeader::Key(key.to_owned()));
+  }
+  SignalData::new(headers, None)
+}
+
+fn state_with(users: &[&str]) -> State {
+  let state = State::new(settings());
+  for username in users {
+    state.get().users.insert(username.to_string(), UserData { address: format!("{username}:1") });
+  }
+  state
+}
+
+#[test]
+fn rename_moves_user_to_the_new_name() {
+  let state = state_with(&["ivan", "anna"]);
+  let mut data = state.get();
+
+  data.rename_user("ivan", "vanya").unwrap();
+  assert!(!data.users.contains_key("ivan"));
+  assert_eq!(data.users["vanya"].address, "ivan:1");
+  assert_eq!(data.users.len(), 2);
+}
+
+#[test]
+fn rename_to_a_taken_name_is_rejected() {
+  let state = state_with(&["ivan", "anna"]);
+  let mut data = state.get();
+
+  let error = data.rename_user("ivan", "anna").unwrap_err();
+  assert_eq!(error.0, "Username anna is already taken");
+  assert_eq!(data.users["ivan"].address, "ivan:1");
+  assert_eq!(data.users["anna"].address, "anna:1");
+  assert!(data.rename_user("ivan", "ivan").is_err());
+}
+
+#[test]
+fn wrong_or_missing_key_is_denied() {
+  let state = State::new(Settings { key: Some("secret".to_owned()), ..settings() });
+  let mut data = state.get();
+
+  let error = data.add_user(&connection_signal("ivan", Some("guess")), "ivan:1").unwrap_err();
+  assert_eq!(error.reason, DenialReason::BadKey);
+  let error = data.add_user(&connection_signal("ivan", None), "ivan:1").unwrap_err();
+  assert_eq!(error.reason, DenialReason::BadKey);
+  assert!(data.users.is_empty());
+
+  data.add_user(&connection_signal("ivan", Some("secret")), "ivan:1").unwrap();
+  assert_eq!(data.users["ivan"].address, "ivan:1");
+}
+
+#[test]
+fn full_server_denies_until_someone_leaves() {
+  let state = State::new(Settings { max_users: 2, ..settings() });
+  let mut data = state.get();
+
+  data.add_user(&connection_signal("ivan", None), "ivan:1").unwrap();
+  data.add_user(&connection_signal("anna", None), "anna:1").unwrap();
+  let error = data.add_user(&connection_signal("petr", None), "petr:1").unwrap_err();
+  assert_eq!(error.reason, DenialReason::ServerFull);
+  assert_eq!(error.text, "Server is full (2/2)");
+  assert_eq!(data.users.len(), 2);
+
+  assert!(data.remove_user("ivan"));
+  assert!(!data.remove_user("ivan"));
+  data.add_user(&connection_signal("petr", None), "petr:1").unwrap();
+  assert!(data.users.contains_key("petr"));
+}
+
+#[test]
+fn spoofed_username_is_rejected() {
+  let message = |username: Option<&str>| {
+    let mut headers = vec![
+      SignalHeader::SignalType(SignalType::NewMessage),
+      SignalHeader::WithMessage
+    ];
+    if let Some(username) = username {
+      headers.push(SignalHeader::Username(username.to_owned()));
+    }
+    SignalData::new(headers, Some("hi"))
+  };
+
+  let error = check_sender("ivan", &message(Some("anna"))).unwrap_err();
+  assert_eq!(error.0, "Username doesn't match the connection");
+  assert!(check_sender("ivan", &message(Some("ivan"))).is_ok());
+  assert!(check_sender("ivan", &message(None)).is_ok());
+}
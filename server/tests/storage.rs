@@ -0,0 +1,177 @@
+use std::{
+  fs,
+  path::PathBuf,
+  time::Duration
+};
+
+use tchat_protocol::DEFAULT_ROOM;
+use tchat_server::{
+  messages_pool::{unix_timestamp, HistoryQuery, MessagesPool, PoolMessage},
+  storage::{Retention, Storage}
+};
+
+fn data_dir(name: &str) -> PathBuf {
+  let dir = std::env::temp_dir().join(format!("tchat-storage-{name}-{}", std::process::id()));
+  fs::remove_dir_all(&dir).ok();
+  dir
+}
+
+fn message(room: &str, text: &str, timestamp: u64) -> PoolMessage {
+  PoolMessage {
+    id: "00000000-0000-0000-0000-000000000000".to_owned(),
+    seq: 1,
+    room: room.to_owned(),
+    username: "ivan".to_owned(),
+    message: text.to_owned(),
+    from_server: false,
+    timestamp
+  }
+}
+
+fn keep_all() -> Retention {
+  Retention { max_messages: usize::MAX, max_age: None }
+}
+
+#[test]
+fn messages_survive_reopening() {
+  let dir = data_dir("reopen");
+  let now = unix_timestamp();
+
+  let mut storage = Storage::open(&dir, keep_all()).unwrap();
+  storage.append(&message(DEFAULT_ROOM, "hello", now)).unwrap();
+  storage.append(&message(DEFAULT_ROOM, "multi\nline\r\n\r\nmessage", now)).unwrap();
+  let mut server_message = message("#rust", "ivan joined #rust", now);
+  server_message.username = String::new();
+  server_message.from_server = true;
+  storage.append(&server_message).unwrap();
+  drop(storage);
+
+  let messages = Storage::open(&dir, keep_all()).unwrap().compact().unwrap();
+  let texts: Vec<&str> = messages.iter().map(|v| v.message.as_str()).collect();
+  assert_eq!(texts, ["hello", "multi\nline\r\n\r\nmessage", "ivan joined #rust"]);
+  assert!(messages[2].from_server);
+  assert_eq!(messages[2].username, "");
+  assert_eq!(messages[2].room, "#rust");
+
+  fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn retention_keeps_latest_messages_per_room() {
+  let dir = data_dir("count");
+  let now = unix_timestamp();
+
+  let mut storage = Storage::open(&dir, Retention { max_messages: 2, max_age: None }).unwrap();
+  for text in ["1", "2", "3"] {
+    storage.append(&message(DEFAULT_ROOM, text, now)).unwrap();
+  }
+  storage.append(&message("#rust", "only", now)).unwrap();
+
+  let messages = storage.compact().unwrap();
+  let texts: Vec<&str> = messages.iter().map(|v| v.message.as_str()).collect();
+  assert_eq!(texts, ["2", "3", "only"]);
+
+  fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn retention_drops_old_messages() {
+  let dir = data_dir("age");
+  let now = unix_timestamp();
+  let retention = Retention { max_messages: usize::MAX, max_age: Some(Duration::from_secs(60 * 60)) };
+
+  let mut storage = Storage::open(&dir, retention).unwrap();
+  storage.append(&message(DEFAULT_ROOM, "old", now - 2 * 60 * 60)).unwrap();
+  storage.append(&message(DEFAULT_ROOM, "new", now - 60)).unwrap();
+
+  let messages = storage.compact().unwrap();
+  assert_eq!(messages.len(), 1);
+  assert_eq!(messages[0].message, "new");
+
+  fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn truncated_record_is_ignored() {
+  let dir = data_dir("truncated");
+  let now = unix_timestamp();
+
+  let mut storage = Storage::open(&dir, keep_all()).unwrap();
+  storage.append(&message(DEFAULT_ROOM, "kept", now)).unwrap();
+  drop(storage);
+  let log = fs::read_dir(&dir).unwrap().next().unwrap().unwrap().path();
+  let mut content = fs::read(&log).unwrap();
+  content.extend_from_slice(format!("{now} 2 0 id #lobby ivan 100\nshort").as_bytes());
+  fs::write(&log, content).unwrap();
+
+  let messages = Storage::open(&dir, keep_all()).unwrap().compact().unwrap();
+  assert_eq!(messages.len(), 1);
+  assert_eq!(messages[0].message, "kept");
+
+  fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn sequence_numbers_continue_after_restart() {
+  let dir = data_dir("seq");
+  let now = unix_timestamp();
+
+  let mut storage = Storage::open(&dir, keep_all()).unwrap();
+  let mut stored = message(DEFAULT_ROOM, "before restart", now);
+  stored.seq = 41;
+  storage.append(&stored).unwrap();
+  drop(storage);
+
+  let storage = Storage::open(&dir, keep_all()).unwrap();
+  let mut pool = MessagesPool::with_storage(16, storage).unwrap();
+  pool.push(message(DEFAULT_ROOM, "after restart", now));
+
+  let query = HistoryQuery { limit: 10, since: None, after: None };
+  let seqs: Vec<u64> = pool.history(DEFAULT_ROOM, &query).messages.iter().map(|v| v.seq).collect();
+  assert_eq!(seqs, [41, 42]);
+
+  fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn pool_writes_messages_in_the_background() {
+  let dir = data_dir("writer");
+  let now = unix_timestamp();
+
+  let mut pool = MessagesPool::with_storage(16, Storage::open(&dir, keep_all()).unwrap()).unwrap();
+  for text in ["one", "two", "three"] {
+    pool.push(message(DEFAULT_ROOM, text, now));
+  }
+  // Пул дожидается записи очереди, когда его удаляют
+  drop(pool);
+
+  let messages = Storage::open(&dir, keep_all()).unwrap().compact().unwrap();
+  let texts: Vec<&str> = messages.iter().map(|v| v.message.as_str()).collect();
+  assert_eq!(texts, ["one", "two", "three"]);
+
+  fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn empty_room_is_restored_from_storage() {
+  let dir = data_dir("restore");
+  let now = unix_timestamp();
+
+  let mut pool = MessagesPool::with_storage(16, Storage::open(&dir, keep_all()).unwrap()).unwrap();
+  let ivan = pool.subscribe("ivan");
+  pool.join("#rust", ivan.id, "ivan");
+  pool.push(message("#rust", "first", now));
+  pool.push(message("#rust", "second", now));
+  pool.part("#rust", ivan.id);
+  assert!(!pool.has_room("#rust"));
+  assert_eq!(pool.room_count(), 1);
+
+  // Сообщения еще могут ждать записи, комната все равно читается целиком
+  pool.join("#rust", ivan.id, "ivan");
+  pool.push(message("#rust", "third", now));
+  let query = HistoryQuery { limit: 10, since: None, after: None };
+  let seqs: Vec<u64> = pool.history("#rust", &query).messages.iter().map(|v| v.seq).collect();
+  assert_eq!(seqs, [1, 2, 3]);
+
+  fs::remove_dir_all(&dir).ok();
+}